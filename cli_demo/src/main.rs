@@ -53,7 +53,7 @@ fn clone_stroke(stroke: &Stroke) -> Stroke {
     res
 }
 
-fn incremental_replay(chars: &Vec<Vec<Stroke>>) -> Vec<Vec<Stroke>> {
+fn incremental_replay(chars: &[Vec<Stroke>]) -> Vec<Vec<Stroke>> {
     let mut res: Vec<Vec<Stroke>> = Vec::new();
     for i in 0..chars.len() {
         let this_char = &chars[i];
@@ -1,11 +1,27 @@
 extern crate serde_derive;
 
 use super::entities::*;
+use super::segmenter::*;
 use super::*;
 
-const MIN_SEGMENT_LENGTH: f32 = 12.5;
-const MAX_LOCAL_LENGTH_RATIO: f32 = 1.1;
-const MAX_RUNNING_LENGTH_RATIO: f32 = 1.09;
+// Side of the canvas that MIN_SEGMENT_LENGTH is measured on; input on other canvases is scaled to it
+pub const REFERENCE_CANVAS_SIZE: f32 = 256.0;
+pub(crate) const MIN_SEGMENT_LENGTH: f32 = 12.5;
+pub(crate) const MAX_LOCAL_LENGTH_RATIO: f32 = 1.1;
+pub(crate) const MAX_RUNNING_LENGTH_RATIO: f32 = 1.09;
+// Segmenters that scale to the character measure lengths against its size, not the canvas's, so they find the same pivots
+// however big the writer writes. Characters written about this big on the reference canvas are measured as drawn.
+const REFERENCE_CHARACTER_SIZE: f32 = 192.0;
+// Tiny drawings, like a first stroke that is a dot, are measured as if they were at least this share of the canvas
+const MIN_PIVOT_SCALE_SHARE: f32 = 0.25;
+// With timestamps, a point where the pen is slower than this fraction of the stroke's mean speed may be a pivot...
+const VELOCITY_PIVOT_RATIO: f64 = 0.3;
+// ...if the stroke also turns there by at least this many radians
+const MIN_VELOCITY_PIVOT_TURN: f32 = 0.5;
+// A stroke shorter than MIN_SEGMENT_LENGTH is a slip, not a dot, if it's over this quickly (milliseconds)...
+const SLIP_MAX_DURATION: f64 = 40.0;
+// ...or the pen barely touched the surface
+const SLIP_MAX_PRESSURE: f32 = 0.1;
 
 struct Rect {
     pub top: f32,
@@ -14,15 +30,51 @@ struct Rect {
     pub right: f32,
 }
 
-pub struct AnalyzedCharacter<'a> {
-    pub analyzed_strokes: Vec<AnalyzedStroke<'a>>,
+pub struct AnalyzedCharacter<'a, P: 'a = Point> {
+    pub analyzed_strokes: Vec<AnalyzedStroke<'a, P>>,
     pub sub_stroke_count: usize,
 }
 
-impl<'a> AnalyzedCharacter<'a> {
-    pub fn from_strokes(strokes: &Vec<Stroke>) -> AnalyzedCharacter {
-        let bounding_rect = get_bounding_rect(strokes);
-        let analyzed_strokes: Vec<AnalyzedStroke> = build_analyzed_strokes(strokes, &bounding_rect);
+impl<'a, P: InputPoint> AnalyzedCharacter<'a, P> {
+    // canvas_size is the side of the square the strokes were drawn in
+    pub fn from_strokes(strokes: &'a [Stroke<P>], canvas_size: f32) -> AnalyzedCharacter<'a, P> {
+        AnalyzedCharacter::from_strokes_with(strokes, canvas_size, &CornerSegmenter::default())
+    }
+
+    // Same as from_strokes, with the given way of splitting strokes into substrokes
+    pub fn from_strokes_with(strokes: &'a [Stroke<P>], canvas_size: f32, segmenter: &dyn Segmenter) -> AnalyzedCharacter<'a, P> {
+        let scale = get_pivot_scale(strokes, canvas_size, segmenter);
+        let pivot_indexes: Vec<Vec<usize>> = strokes.iter().map(|stroke| get_pivot_indexes(stroke, segmenter, scale)).collect();
+        AnalyzedCharacter::from_strokes_with_pivots(strokes, pivot_indexes, canvas_size)
+    }
+
+    // Pivots only depend on the stroke itself and the scale, so callers may compute them once per stroke and keep them
+    // while get_pivot_scale stays the same.
+    // Slips of the pen are left out entirely, so they count neither as strokes nor towards the bounding rectangle
+    pub fn from_strokes_with_pivots(strokes: &'a [Stroke<P>], pivot_indexes: Vec<Vec<usize>>, canvas_size: f32) -> AnalyzedCharacter<'a, P> {
+        let kept: Vec<bool> = strokes.iter().map(|stroke| !is_slip(stroke, canvas_size)).collect();
+        let bounding_rect = get_bounding_rect(strokes, &kept, canvas_size);
+        AnalyzedCharacter::from_kept_strokes(strokes, pivot_indexes, &kept, &bounding_rect)
+    }
+
+    // Normalizes against the whole canvas instead of the strokes' bounding box.
+    // For strokes traced over a character shown on the canvas: until it is finished, the strokes don't span the character.
+    // Pivot detection is scaled to the canvas too.
+    pub fn from_strokes_on_canvas(strokes: &'a [Stroke<P>], canvas_size: f32, segmenter: &dyn Segmenter) -> AnalyzedCharacter<'a, P> {
+        let scale = canvas_size / REFERENCE_CANVAS_SIZE;
+        let pivot_indexes: Vec<Vec<usize>> = strokes.iter().map(|stroke| get_pivot_indexes(stroke, segmenter, scale)).collect();
+        let kept: Vec<bool> = strokes.iter().map(|stroke| !is_slip(stroke, canvas_size)).collect();
+        let canvas_rect = Rect {
+            top: 0f32,
+            bottom: canvas_size,
+            left: 0f32,
+            right: canvas_size,
+        };
+        AnalyzedCharacter::from_kept_strokes(strokes, pivot_indexes, &kept, &canvas_rect)
+    }
+
+    fn from_kept_strokes(strokes: &'a [Stroke<P>], pivot_indexes: Vec<Vec<usize>>, kept: &[bool], bounding_rect: &Rect) -> AnalyzedCharacter<'a, P> {
+        let analyzed_strokes: Vec<AnalyzedStroke<P>> = build_analyzed_strokes(strokes, pivot_indexes, kept, bounding_rect);
         let mut sub_stroke_count: usize = 0;
         for i in 0..analyzed_strokes.len() {
             sub_stroke_count += analyzed_strokes[i].sub_strokes.len();
@@ -45,15 +97,15 @@ impl<'a> AnalyzedCharacter<'a> {
 }
 
 // Gets distance between two points
-fn dist(a: Point, b: Point) -> f32 {
-    let dx = (a.x as f32) - (b.x as f32);
-    let dy = (a.y as f32) - (b.y as f32);
+fn dist<P: InputPoint>(a: P, b: P) -> f32 {
+    let dx = a.x() - b.x();
+    let dy = a.y() - b.y();
     (dx * dx + dy * dy).sqrt()
 }
 
 // Gets normalized distance between two points
 // Normalized based on bounding rectangle
-fn norm_dist(a: Point, b: Point, bounding_rect: &Rect) -> f32 {
+fn norm_dist<P: InputPoint>(a: P, b: P, bounding_rect: &Rect) -> f32 {
     let width = bounding_rect.right - bounding_rect.left;
     let height = bounding_rect.bottom - bounding_rect.top;
     // normalizer is a diagonal along a square with sides of size the larger dimension of the bounding box
@@ -68,16 +120,16 @@ fn norm_dist(a: Point, b: Point, bounding_rect: &Rect) -> f32 {
 
 // Gets direction, in radians, from point a to b
 // 0 is to the right, PI / 2 is up, etc.
-fn dir(a: Point, b: Point) -> f32 {
-    let dx = (a.x as f32) - (b.x as f32);
-    let dy = (a.y as f32) - (b.y as f32);
+fn dir<P: InputPoint>(a: P, b: P) -> f32 {
+    let dx = a.x() - b.x();
+    let dy = a.y() - b.y();
     let dir = dy.atan2(dx);
     std::f32::consts::PI - dir
 }
 
-fn get_norm_center(a: Point, b: Point, bounding_rect: &Rect) -> (f32, f32) {
-    let mut x = ((a.x as f32) + (b.x as f32)) / 2f32;
-    let mut y = ((a.y as f32) + (b.y as f32)) / 2f32;
+fn get_norm_center<P: InputPoint>(a: P, b: P, bounding_rect: &Rect) -> (f32, f32) {
+    let mut x = (a.x() + b.x()) / 2f32;
+    let mut y = (a.y() + b.y()) / 2f32;
     let side;
     // Bounding rect is landscape
     if bounding_rect.right - bounding_rect.left > bounding_rect.bottom - bounding_rect.top {
@@ -96,10 +148,37 @@ fn get_norm_center(a: Point, b: Point, bounding_rect: &Rect) -> (f32, f32) {
     (x / side, y / side)
 }
 
+// Input units per unit of the reference canvas that pivot detection measures lengths in:
+// from the size of the canvas, or of the character if the segmenter scales to it
+pub fn get_pivot_scale<P: InputPoint>(strokes: &[Stroke<P>], canvas_size: f32, segmenter: &dyn Segmenter) -> f32 {
+    if !segmenter.scales_to_character() {
+        return canvas_size / REFERENCE_CANVAS_SIZE;
+    }
+    let kept: Vec<bool> = strokes.iter().map(|stroke| !is_slip(stroke, canvas_size)).collect();
+    let rect = get_bounding_rect(strokes, &kept, canvas_size);
+    let side = f32::max(rect.right - rect.left, rect.bottom - rect.top);
+    f32::max(side, MIN_PIVOT_SCALE_SHARE * canvas_size) / REFERENCE_CHARACTER_SIZE
+}
+
 // Calculates array with indexes of pivot points in raw stroke
-fn get_pivot_indexes(stroke: &Stroke) -> Vec<usize> {
+pub fn get_pivot_indexes<P: InputPoint>(stroke: &Stroke<P>, segmenter: &dyn Segmenter, scale: f32) -> Vec<usize> {
+    let points: Vec<PenPoint> = stroke.points.iter().map(|point| PenPoint {
+        x: point.x(),
+        y: point.y(),
+        time: point.time(),
+        pressure: point.pressure(),
+    }).collect();
+    segmenter.pivot_indexes(&points, scale)
+}
 
-    let points = &stroke.points;
+// The classic corner and curve heuristic: a point is a pivot where the way there from the previous pivot,
+// or the way around it, gets too much longer than the straight line
+pub(crate) fn get_corner_pivots<P: InputPoint>(points: &[P], min_segment_length: f32, max_local_length_ratio: f32, max_running_length_ratio: f32) -> Vec<usize> {
+
+    // Too short for the algorithm below; a single point is its own only pivot
+    if points.len() < 2 {
+        return (0..points.len()).collect();
+    }
 
     // One item for each point: true if it's a pivot
     let mut markers: Vec<bool> = Vec::with_capacity(points.len());
@@ -144,11 +223,11 @@ fn get_pivot_indexes(stroke: &Stroke) -> Vec<usize> {
         // mark the point as a pivot.
         let dist_from_previous = dist(points[prev_pt_ix], next_point);
         let dist_from_first = dist(points[first_pt_ix], next_point);
-        if  local_length > MAX_LOCAL_LENGTH_RATIO * dist_from_previous || 
-            running_length > MAX_RUNNING_LENGTH_RATIO * dist_from_first {
+        if  local_length > max_local_length_ratio * dist_from_previous || 
+            running_length > max_running_length_ratio * dist_from_first {
             // If the previous point was a pivot and was very close to this point,
             // which we are about to mark as a pivot, then unmark the previous point as a pivot.
-            if markers[prev_pt_ix] && dist(points[prev_pt_ix], points[pivot_pt_ix]) < MIN_SEGMENT_LENGTH {
+            if markers[prev_pt_ix] && dist(points[prev_pt_ix], points[pivot_pt_ix]) < min_segment_length {
                 markers[prev_pt_ix] = false;
             }
             markers[pivot_pt_ix] = true;
@@ -169,10 +248,13 @@ fn get_pivot_indexes(stroke: &Stroke) -> Vec<usize> {
     // We'll want to unmark the previous point if it's also a pivot and very close to the lat point.
     // However if the previous point is the first point of the stroke, then don't unmark it, because
     // then we'd only have one pivot.
-    if markers[prev_pt_ix] && dist(points[prev_pt_ix], points[pivot_pt_ix]) < MIN_SEGMENT_LENGTH && prev_pt_ix != 0 {
+    if markers[prev_pt_ix] && dist(points[prev_pt_ix], points[pivot_pt_ix]) < min_segment_length && prev_pt_ix != 0 {
         markers[prev_pt_ix] = false;
     }
 
+    // Pen input with timestamps: the pen slows down at corners too gentle for the ratios above
+    add_velocity_pivots(points, &mut markers, min_segment_length);
+
     // Return result in the form of an index array: includes indexes where marker is true
     let mut marker_count = 0;
     for x in &markers {
@@ -189,8 +271,103 @@ fn get_pivot_indexes(stroke: &Stroke) -> Vec<usize> {
     res
 }
 
+// Marks points where the pen slows down markedly while changing direction
+// Does nothing unless every point has a timestamp
+fn add_velocity_pivots<P: InputPoint>(points: &[P], markers: &mut [bool], min_segment_length: f32) {
+    let count = points.len();
+    if count < 3 {
+        return;
+    }
+    let mut times: Vec<f64> = Vec::with_capacity(count);
+    for pt in points {
+        match pt.time() {
+            Some(time) if time.is_finite() => times.push(time),
+            _ => return,
+        }
+    }
+    let duration = times[count - 1] - times[0];
+    if duration <= 0f64 {
+        return;
+    }
+    let mut length = 0f32;
+    for i in 1..count {
+        length += dist(points[i - 1], points[i]);
+    }
+    let max_speed = VELOCITY_PIVOT_RATIO * length as f64 / duration;
+
+    // Speed at each inner point, measured over its two neighbors; None where the timestamps don't advance
+    let mut speeds: Vec<Option<f64>> = vec![None; count];
+    for i in 1..count - 1 {
+        let elapsed = times[i + 1] - times[i - 1];
+        if elapsed > 0f64 {
+            speeds[i] = Some((dist(points[i - 1], points[i]) + dist(points[i], points[i + 1])) as f64 / elapsed);
+        }
+    }
+
+    for i in 1..count - 1 {
+        // Slow enough, and the slowest among its neighbors
+        let speed = match speeds[i] {
+            Some(speed) if speed < max_speed => speed,
+            _ => continue,
+        };
+        if speeds[i - 1].map_or(false, |prev| prev < speed) || speeds[i + 1].map_or(false, |next| next <= speed) {
+            continue;
+        }
+        // Direction is measured over a full segment length on either side, so jitter doesn't count as a turn
+        let before = (0..i).rev().find(|&ix| dist(points[ix], points[i]) >= min_segment_length);
+        let after = (i + 1..count).find(|&ix| dist(points[i], points[ix]) >= min_segment_length);
+        let (before, after) = match (before, after) {
+            (Some(before), Some(after)) => (before, after),
+            _ => continue,
+        };
+        // A pivot close by already covers this corner
+        if markers[before + 1..after].iter().any(|&marker| marker) {
+            continue;
+        }
+        let mut turn = (dir(points[before], points[i]) - dir(points[i], points[after])).abs();
+        if turn > std::f32::consts::PI {
+            turn = 2f32 * std::f32::consts::PI - turn;
+        }
+        if turn >= MIN_VELOCITY_PIVOT_TURN {
+            markers[i] = true;
+        }
+    }
+}
+
+// Tells a slip of the pen from a deliberate dot; without timestamps or pressure, nothing is a slip
+fn is_slip<P: InputPoint>(stroke: &Stroke<P>, canvas_size: f32) -> bool {
+    let mut length = 0f32;
+    for i in 1..stroke.points.len() {
+        length += dist(stroke.points[i - 1], stroke.points[i]);
+    }
+    if length >= MIN_SEGMENT_LENGTH * canvas_size / REFERENCE_CANVAS_SIZE {
+        return false;
+    }
+    let too_brief = get_duration(&stroke.points).map_or(false, |duration| duration < SLIP_MAX_DURATION);
+    let too_light = get_mean_pressure(&stroke.points).map_or(false, |pressure| pressure < SLIP_MAX_PRESSURE);
+    too_brief || too_light
+}
+
+fn get_duration<P: InputPoint>(points: &[P]) -> Option<f64> {
+    let first = points.first()?.time()?;
+    let last = points.last()?.time()?;
+    if points.iter().all(|pt| pt.time().is_some()) { Some(last - first) } else { None }
+}
+
+fn get_mean_pressure<P: InputPoint>(points: &[P]) -> Option<f32> {
+    let mut sum = 0f32;
+    let mut count = 0;
+    for pt in points {
+        if let Some(pressure) = pt.pressure() {
+            sum += pressure;
+            count += 1;
+        }
+    }
+    if count == 0 { None } else { Some(sum / count as f32) }
+}
+
 // Builds array of substrokes from stroke's points, pivots, and character's bounding rectangle
-fn build_sub_strokes(stroke: &Stroke, pivot_indexes: &Vec<usize>, bounding_rect: &Rect) -> Vec<SubStroke> {
+fn build_sub_strokes<P: InputPoint>(stroke: &Stroke<P>, pivot_indexes: &[usize], bounding_rect: &Rect) -> Vec<SubStroke> {
     let mut res: Vec<SubStroke> = Vec::new();
     let mut prev_ix: usize = 0;
     for i in 0..pivot_indexes.len() {
@@ -214,43 +391,45 @@ fn build_sub_strokes(stroke: &Stroke, pivot_indexes: &Vec<usize>, bounding_rect:
 }
 
 // Analyze raw input, store result in _analyzedStrokes member.
-fn build_analyzed_strokes<'a>(strokes: &'a Vec<Stroke>, bounding_rect: &Rect) -> Vec<AnalyzedStroke<'a>> {
-    let mut res: Vec<AnalyzedStroke> = Vec::new();
-    // Process each stroke
-    for stroke in strokes {
-        // Identify pivot points
-        let pivot_indexes = get_pivot_indexes(stroke);
+fn build_analyzed_strokes<'a, P: InputPoint>(strokes: &'a [Stroke<P>], pivot_indexes: Vec<Vec<usize>>, kept: &[bool], bounding_rect: &Rect) -> Vec<AnalyzedStroke<'a, P>> {
+    let mut res: Vec<AnalyzedStroke<P>> = Vec::new();
+    // Process each stroke, with its pivot points already identified
+    for (index, (stroke, pivot_indexes)) in strokes.iter().zip(pivot_indexes).enumerate() {
+        if !kept[index] { continue; }
         // Abstract away substrokes
         let sub_strokes = build_sub_strokes(stroke, &pivot_indexes, bounding_rect);
         // Store all this
         res.push(AnalyzedStroke{
+            index,
             points: &stroke.points,
             pivot_indexes: pivot_indexes,
             sub_strokes: sub_strokes,
+            duration: get_duration(&stroke.points),
+            pressure: get_mean_pressure(&stroke.points),
         });
     }
     res
 }
 
-fn get_bounding_rect(strokes: &Vec<Stroke>) -> Rect {
+fn get_bounding_rect<P: InputPoint>(strokes: &[Stroke<P>], kept: &[bool], canvas_size: f32) -> Rect {
     let mut res = Rect {
         top: std::f32::MAX,
         bottom: std::f32::MIN,
         left: std::f32::MAX,
         right: std::f32::MIN,
     };
-    for stroke in strokes {
+    for (stroke, keep) in strokes.iter().zip(kept.iter()) {
+        if !keep { continue; }
         for pt in &stroke.points {
-            if (pt.x as f32) < res.left { res.left = pt.x as f32; }
-            if (pt.x as f32) > res.right { res.right = pt.x as f32; }
-            if (pt.y as f32) < res.top { res.top = pt.y as f32; }
-            if (pt.y as f32) > res.bottom { res.bottom = pt.y as f32; }
+            if pt.x() < res.left { res.left = pt.x(); }
+            if pt.x() > res.right { res.right = pt.x(); }
+            if pt.y() < res.top { res.top = pt.y(); }
+            if pt.y() > res.bottom { res.bottom = pt.y(); }
         }
     }
-    if res.top > 255f32 { res.top = 0f32; }
-    if res.bottom < 0f32 { res.bottom = 255f32; }
-    if res.left > 255f32 { res.left = 0f32; }
-    if res.right < 0f32 { res.right = 255f32; }
+    // No points at all: whole canvas
+    if res.top > res.bottom { res.top = 0f32; res.bottom = canvas_size - 1f32; }
+    if res.left > res.right { res.left = 0f32; res.right = canvas_size - 1f32; }
     res
 }
 
@@ -260,7 +439,8 @@ mod tests {
     use serde_derive::{Serialize, Deserialize};
 
     use super::*;
-    use super::super::Point;
+    use super::super::{test_samples, PenPoint, Point};
+    use super::super::test_samples::{STROKES_1, STROKES_2, STROKES_3, STROKES_4};
 
     #[derive(Serialize, Deserialize)]
     struct SampleAnSubStroke {
@@ -289,39 +469,21 @@ mod tests {
 
     // These manual samples are custom-saved from a tweaked version of the HanziLookupJS demo
 
-    // This is a hand-drawn 一
-    static STROKES_1: &str = "[[[70,124],[71,124],[79,124],[104,124],[119,124],[132,125],[151,126],[168,126],[169,126],[189,125],[191,124],[191,124]]]";
+    // Analysis of the hand-drawn 一 by HanziLookupJS
     static AN_CHAR_1: &str = "{\"top\":124,\"bottom\":126,\"left\":70,\"right\":191,\"analyzedStrokes\":[{\"points\":[[70,124],[71,124],[79,124],[104,124],[119,124],[132,125],[151,126],[168,126],[169,126],[189,125],[191,124],[191,124]],\"pivotIndexes\":[0,11],\"subStrokes\":[{\"direction\":0,\"length\":180,\"centerX\":8,\"centerY\":7}]}],\"subStrokeCount\":1}";
 
-    // This is a hand-drawn 十
-    static STROKES_2: &str = "[[[76,127],[77,127],[84,127],[97,128],[119,128],[125,129],[138,130],[147,130],[153,131],[154,131],[158,131],[162,131],[167,131],[168,131],[169,131],[169,131]],[[129,60],[129,62],[128,74],[128,102],[128,118],[129,143],[130,162],[130,170],[130,178],[131,184],[131,188],[131,193],[131,196],[131,198],[131,203],[131,203]]]";
+    // Analysis of the hand-drawn 十 by HanziLookupJS
     static AN_CHAR_2: &str = "{\"top\":60,\"bottom\":203,\"left\":76,\"right\":169,\"analyzedStrokes\":[{\"points\":[[76,127],[77,127],[84,127],[97,128],[119,128],[125,129],[138,130],[147,130],[153,131],[154,131],[158,131],[162,131],[167,131],[168,131],[169,131],[169,131]],\"pivotIndexes\":[0,15],\"subStrokes\":[{\"direction\":254,\"length\":117,\"centerX\":8,\"centerY\":7}]},{\"points\":[[129,60],[129,62],[128,74],[128,102],[128,118],[129,143],[130,162],[130,170],[130,178],[131,184],[131,188],[131,193],[131,196],[131,198],[131,203],[131,203]],\"pivotIndexes\":[0,15],\"subStrokes\":[{\"direction\":193,\"length\":180,\"centerX\":8,\"centerY\":8}]}],\"subStrokeCount\":2}";
 
-    // This is a hand-drawn 元
-    static STROKES_3: &str = "[[[86,65],[98,66],[146,69],[152,69],[161,69],[166,69],[170,68],[170,68]],[[47,97],[48,97],[54,97],[89,103],[117,104],[146,101],[169,100],[176,98],[180,98],[184,98],[189,98],[193,98],[195,98],[195,98]],[[103,109],[103,110],[99,132],[91,156],[70,180],[56,190],[53,192]],[[143,105],[143,106],[142,114],[140,134],[138,149],[138,160],[138,167],[140,174],[144,182],[150,186],[155,190],[161,193],[166,194],[172,196],[188,197],[193,197],[197,197],[206,197],[206,196],[207,196],[208,196],[208,194],[204,182],[203,174],[202,174],[202,175],[202,176]]]";
+    // Analysis of the hand-drawn 元 by HanziLookupJS
     static AN_CHAR_3: &str = "{\"top\":65,\"bottom\":197,\"left\":47,\"right\":208,\"analyzedStrokes\":[{\"points\":[[86,65],[98,66],[146,69],[152,69],[161,69],[166,69],[170,68],[170,68]],\"pivotIndexes\":[0,7],\"subStrokes\":[{\"direction\":255,\"length\":94,\"centerX\":8,\"centerY\":1}]},{\"points\":[[47,97],[48,97],[54,97],[89,103],[117,104],[146,101],[169,100],[176,98],[180,98],[184,98],[189,98],[193,98],[195,98],[195,98]],\"pivotIndexes\":[0,13],\"subStrokes\":[{\"direction\":0,\"length\":166,\"centerX\":7,\"centerY\":4}]},{\"points\":[[103,109],[103,110],[99,132],[91,156],[70,180],[56,190],[53,192]],\"pivotIndexes\":[0,6],\"subStrokes\":[{\"direction\":170,\"length\":109,\"centerX\":3,\"centerY\":9}]},{\"points\":[[143,105],[143,106],[142,114],[140,134],[138,149],[138,160],[138,167],[140,174],[144,182],[150,186],[155,190],[161,193],[166,194],[172,196],[188,197],[193,197],[197,197],[206,197],[206,196],[207,196],[208,196],[208,194],[204,182],[203,174],[202,174],[202,175],[202,176]],\"pivotIndexes\":[0,10,18,20,24,26],\"subStrokes\":[{\"direction\":198,\"length\":96,\"centerX\":10,\"centerY\":9},{\"direction\":251,\"length\":58,\"centerX\":12,\"centerY\":13},{\"direction\":0,\"length\":2,\"centerX\":15,\"centerY\":14},{\"direction\":75,\"length\":26,\"centerX\":15,\"centerY\":13},{\"direction\":192,\"length\":2,\"centerX\":14,\"centerY\":12}]}],\"subStrokeCount\":8}";
 
-    // This is a hand-drawn 氣
-    static STROKES_4: &str = "[[[76,32],[76,33],[75,37],[73,43],[70,51],[67,58],[64,66],[61,72],[57,77],[52,82],[50,85],[50,85]],[[68,58],[69,58],[76,58],[90,59],[100,60],[110,62],[118,62],[132,62],[136,62],[141,62],[145,62],[146,62],[148,62],[148,62]],[[68,95],[69,95],[77,96],[96,96],[105,96],[110,96],[126,97],[144,98],[146,98],[154,98],[156,98],[156,98]],[[59,126],[60,126],[67,126],[90,130],[107,131],[120,132],[134,132],[149,132],[151,132],[156,132],[158,133],[158,134],[156,142],[154,147],[153,155],[152,160],[151,166],[150,172],[150,179],[150,183],[150,186],[150,190],[151,194],[152,199],[156,204],[158,206],[162,209],[167,213],[171,215],[175,216],[184,220],[192,222],[196,223],[200,224],[204,225],[208,225],[210,225],[214,225],[218,223],[218,222],[216,214],[214,208],[214,207],[214,207]],[[79,147],[82,148],[87,155],[91,161],[91,161]],[[124,148],[123,148],[116,155],[110,162],[108,164],[108,164]],[[73,175],[75,175],[88,178],[98,180],[104,180],[111,182],[117,182],[122,182],[125,182]],[[100,148],[100,151],[102,172],[102,195],[103,204],[103,211],[104,216],[104,220],[104,224]],[[94,189],[93,189],[81,204],[72,210],[71,210]],[[109,192],[112,194],[120,199],[132,208],[133,210],[133,210]]]";
+    // Analysis of the hand-drawn 氣 by HanziLookupJS
     static AN_CHAR_4: &str = "{\"top\":32,\"bottom\":225,\"left\":50,\"right\":218,\"analyzedStrokes\":[{\"points\":[[76,32],[76,33],[75,37],[73,43],[70,51],[67,58],[64,66],[61,72],[57,77],[52,82],[50,85],[50,85]],\"pivotIndexes\":[0,11],\"subStrokes\":[{\"direction\":173,\"length\":55,\"centerX\":2,\"centerY\":2}]},{\"points\":[[68,58],[69,58],[76,58],[90,59],[100,60],[110,62],[118,62],[132,62],[136,62],[141,62],[145,62],[146,62],[148,62],[148,62]],\"pivotIndexes\":[0,13],\"subStrokes\":[{\"direction\":254,\"length\":75,\"centerX\":5,\"centerY\":2}]},{\"points\":[[68,95],[69,95],[77,96],[96,96],[105,96],[110,96],[126,97],[144,98],[146,98],[154,98],[156,98],[156,98]],\"pivotIndexes\":[0,11],\"subStrokes\":[{\"direction\":255,\"length\":82,\"centerX\":6,\"centerY\":5}]},{\"points\":[[59,126],[60,126],[67,126],[90,130],[107,131],[120,132],[134,132],[149,132],[151,132],[156,132],[158,133],[158,134],[156,142],[154,147],[153,155],[152,160],[151,166],[150,172],[150,179],[150,183],[150,186],[150,190],[151,194],[152,199],[156,204],[158,206],[162,209],[167,213],[171,215],[175,216],[184,220],[192,222],[196,223],[200,224],[204,225],[208,225],[210,225],[214,225],[218,223],[218,222],[216,214],[214,208],[214,207],[214,207]],\"pivotIndexes\":[0,10,26,39,43],\"subStrokes\":[{\"direction\":253,\"length\":93,\"centerX\":6,\"centerY\":8},{\"direction\":194,\"length\":71,\"centerX\":10,\"centerY\":11},{\"direction\":247,\"length\":54,\"centerX\":12,\"centerY\":14},{\"direction\":75,\"length\":15,\"centerX\":14,\"centerY\":14}]},{\"points\":[[79,147],[82,148],[87,155],[91,161],[91,161]],\"pivotIndexes\":[0,4],\"subStrokes\":[{\"direction\":221,\"length\":17,\"centerX\":4,\"centerY\":9}]},{\"points\":[[124,148],[123,148],[116,155],[110,162],[108,164],[108,164]],\"pivotIndexes\":[0,5],\"subStrokes\":[{\"direction\":160,\"length\":21,\"centerX\":6,\"centerY\":10}]},{\"points\":[[73,175],[75,175],[88,178],[98,180],[104,180],[111,182],[117,182],[122,182],[125,182]],\"pivotIndexes\":[0,8],\"subStrokes\":[{\"direction\":251,\"length\":49,\"centerX\":5,\"centerY\":11}]},{\"points\":[[100,148],[100,151],[102,172],[102,195],[103,204],[103,211],[104,216],[104,220],[104,224]],\"pivotIndexes\":[0,8],\"subStrokes\":[{\"direction\":194,\"length\":71,\"centerX\":5,\"centerY\":12}]},{\"points\":[[94,189],[93,189],[81,204],[72,210],[71,210]],\"pivotIndexes\":[0,4],\"subStrokes\":[{\"direction\":158,\"length\":29,\"centerX\":3,\"centerY\":13}]},{\"points\":[[109,192],[112,194],[120,199],[132,208],[133,210],[133,210]],\"pivotIndexes\":[0,5],\"subStrokes\":[{\"direction\":230,\"length\":28,\"centerX\":6,\"centerY\":13}]}],\"subStrokeCount\":13}";
 
     fn parse_sample(str_strokes: &str, str_an_char: &str) -> (Vec<Stroke>, SampleAnChar) {
-        let vec_strokes: Vec<Vec<Vec<u8>>> = serde_json::from_str(str_strokes).unwrap();
-        let mut strokes: Vec<Stroke> = Vec::new();
-        for vec_stroke in &vec_strokes {
-            let mut points: Vec<Point> = Vec::new();
-            for vec_point in vec_stroke {
-                points.push(Point {
-                    x: vec_point[0],
-                    y: vec_point[1],
-                });
-            }
-            strokes.push(Stroke {
-                points: points,
-            });
-        }
         let an_char: SampleAnChar = serde_json::from_str(str_an_char).unwrap();
-        (strokes, an_char)
+        (test_samples::parse_sample(str_strokes), an_char)
     }
 
     fn assert_same(sample_anc: &SampleAnChar, anc: &AnalyzedCharacter) {
@@ -356,27 +518,124 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_short_strokes() {
+        let strokes = vec![
+            Stroke { points: vec![] },
+            Stroke { points: vec![Point { x: 10, y: 10 }] },
+            Stroke { points: vec![Point { x: 10, y: 10 }, Point { x: 100, y: 10 }] },
+        ];
+        let anc = AnalyzedCharacter::from_strokes(&strokes, REFERENCE_CANVAS_SIZE);
+        assert_eq!(anc.analyzed_strokes[0].pivot_indexes.len(), 0);
+        assert_eq!(anc.analyzed_strokes[1].pivot_indexes, [0]);
+        assert_eq!(anc.analyzed_strokes[1].sub_strokes.len(), 0);
+        assert_eq!(anc.analyzed_strokes[2].pivot_indexes, [0, 1]);
+        assert_eq!(anc.sub_stroke_count, 1);
+    }
+
+    // Pen points along a polyline, sampled every 8 units, 10 ms apart except for pauses before the given indexes
+    fn pen_stroke(start: (f32, f32), angles: &[f32], steps: usize, pauses: &[usize], pressure: Option<f32>) -> Stroke<PenPoint> {
+        let mut points = vec![PenPoint { x: start.0, y: start.1, time: Some(0.0), pressure: pressure }];
+        for angle in angles {
+            for _ in 0..steps {
+                let prev = points[points.len() - 1];
+                let time = prev.time.unwrap() + if pauses.contains(&points.len()) { 100.0 } else { 10.0 };
+                points.push(PenPoint { x: prev.x + 8.0 * angle.cos(), y: prev.y + 8.0 * angle.sin(), time: Some(time), pressure: pressure });
+            }
+        }
+        Stroke { points: points }
+    }
+
+    #[test]
+    fn test_velocity_pivots() {
+        // Bend of 35 degrees is too gentle for the length ratios, but the pen pauses there
+        let angles = [0.0, 35f32.to_radians()];
+        let slow = pen_stroke((20.0, 60.0), &angles, 9, &[9, 10], None);
+        assert_eq!(get_pivot_indexes(&slow, &CornerSegmenter::default(), 1.0), [0, 9, 18]);
+        // Same shape drawn at an even pace
+        let even = pen_stroke((20.0, 60.0), &angles, 9, &[], None);
+        assert_eq!(get_pivot_indexes(&even, &CornerSegmenter::default(), 1.0), [0, 18]);
+        // Without timestamps, only geometry counts
+        let untimed = Stroke { points: slow.points.iter().map(|pt| PenPoint { time: None, ..*pt }).collect() };
+        assert_eq!(get_pivot_indexes(&untimed, &CornerSegmenter::default(), 1.0), [0, 18]);
+        // Pausing on a straight line is not a corner
+        let straight = pen_stroke((20.0, 60.0), &[0.0, 0.0], 9, &[9, 10], None);
+        assert_eq!(get_pivot_indexes(&straight, &CornerSegmenter::default(), 1.0), [0, 18]);
+    }
+
+    #[test]
+    fn test_slips() {
+        let line = pen_stroke((20.0, 60.0), &[0.0], 20, &[], Some(0.5));
+        // 8 units long, over in 10 ms
+        let slip = pen_stroke((200.0, 200.0), &[1.0], 1, &[], Some(0.5));
+        // Same length, but a deliberate 100 ms dot
+        let dot = pen_stroke((200.0, 200.0), &[1.0], 1, &[1], Some(0.5));
+        // Deliberate pace, but the pen barely touched the surface
+        let graze = pen_stroke((200.0, 200.0), &[1.0], 1, &[1], Some(0.05));
+
+        let strokes = vec![Stroke { points: line.points.clone() }, slip];
+        let anc = AnalyzedCharacter::from_strokes(&strokes, REFERENCE_CANVAS_SIZE);
+        assert_eq!(anc.analyzed_strokes.len(), 1);
+        // The slip doesn't stretch the bounding rectangle either
+        let alone = vec![Stroke { points: line.points.clone() }];
+        assert_eq!(anc.get_analyzed_strokes(), AnalyzedCharacter::from_strokes(&alone, REFERENCE_CANVAS_SIZE).get_analyzed_strokes());
+
+        let strokes = vec![Stroke { points: line.points.clone() }, dot];
+        let anc = AnalyzedCharacter::from_strokes(&strokes, REFERENCE_CANVAS_SIZE);
+        assert_eq!(anc.analyzed_strokes.len(), 2);
+        assert_eq!(anc.analyzed_strokes[1].index, 1);
+        assert_eq!(anc.analyzed_strokes[1].duration, Some(100.0));
+        assert_eq!(anc.analyzed_strokes[1].pressure, Some(0.5));
+
+        let strokes = vec![Stroke { points: line.points.clone() }, graze];
+        assert_eq!(AnalyzedCharacter::from_strokes(&strokes, REFERENCE_CANVAS_SIZE).analyzed_strokes.len(), 1);
+    }
+
     #[test]
     fn test_samples() {
         {
             let sample = parse_sample(STROKES_1, AN_CHAR_1);
-            let ac = AnalyzedCharacter::from_strokes(&sample.0);
+            let ac = AnalyzedCharacter::from_strokes(&sample.0, REFERENCE_CANVAS_SIZE);
             assert_same(&sample.1, &ac);
         }
         {
             let sample = parse_sample(STROKES_2, AN_CHAR_2);
-            let ac = AnalyzedCharacter::from_strokes(&sample.0);
+            let ac = AnalyzedCharacter::from_strokes(&sample.0, REFERENCE_CANVAS_SIZE);
             assert_same(&sample.1, &ac);
         }
         {
             let sample = parse_sample(STROKES_3, AN_CHAR_3);
-            let ac = AnalyzedCharacter::from_strokes(&sample.0);
+            let ac = AnalyzedCharacter::from_strokes(&sample.0, REFERENCE_CANVAS_SIZE);
             assert_same(&sample.1, &ac);
         }
         {
             let sample = parse_sample(STROKES_4, AN_CHAR_4);
-            let ac = AnalyzedCharacter::from_strokes(&sample.0);
+            let ac = AnalyzedCharacter::from_strokes(&sample.0, REFERENCE_CANVAS_SIZE);
             assert_same(&sample.1, &ac);
         }
     }
+
+    #[test]
+    fn test_pivot_scale() {
+        let sample = parse_sample(STROKES_4, AN_CHAR_4);
+        let scaled = |factor: f32| -> Vec<Stroke<PenPoint>> {
+            sample.0.iter().map(|stroke| Stroke {
+                points: stroke.points.iter().map(|pt| PenPoint { x: pt.x() * factor, y: pt.y() * factor, time: None, pressure: None }).collect(),
+            }).collect()
+        };
+        let (full, half) = (scaled(1.0), scaled(0.5));
+        // By default, lengths are measured on the canvas, whatever the size of the character
+        let canvas = CornerSegmenter::default();
+        assert_eq!(get_pivot_scale(&half, REFERENCE_CANVAS_SIZE, &canvas), 1.0);
+        assert_eq!(get_pivot_scale(&full, 2.0 * REFERENCE_CANVAS_SIZE, &canvas), 2.0);
+        // Scaled to the character, 氣 written at half the size, in the canvas's top left corner, has the same substrokes
+        let character = CornerSegmenter { scale_to_character: true, ..CornerSegmenter::default() };
+        assert_eq!(get_pivot_scale(&half, REFERENCE_CANVAS_SIZE, &character), get_pivot_scale(&full, REFERENCE_CANVAS_SIZE, &character) / 2.0);
+        let full_char = AnalyzedCharacter::from_strokes_with(&full, REFERENCE_CANVAS_SIZE, &character);
+        let half_char = AnalyzedCharacter::from_strokes_with(&half, REFERENCE_CANVAS_SIZE, &character);
+        assert_eq!(half_char.get_analyzed_strokes(), full_char.get_analyzed_strokes());
+        // A dot on its own is not blown up to the size of a character
+        let dot = vec![Stroke { points: vec![PenPoint { x: 10.0, y: 10.0, time: None, pressure: None }, PenPoint { x: 12.0, y: 12.0, time: None, pressure: None }] }];
+        assert_eq!(get_pivot_scale(&dot, REFERENCE_CANVAS_SIZE, &character), get_pivot_scale(&dot, 2.0 * REFERENCE_CANVAS_SIZE, &character) / 2.0);
+    }
 }
@@ -0,0 +1,73 @@
+use super::dataset::*;
+use super::matcher::*;
+
+// A drawn component may skip this many of the character's strokes in between its own, e.g. where another part cuts through it
+const MAX_COMPONENT_GAP: usize = 1;
+
+// Runs of the template's strokes that the drawn component could be, each as a template of its own,
+// with its substrokes measured within the run's own bounding box the way input is
+pub(crate) fn get_component_windows(char_data: &CharData, stroke_count: usize) -> Vec<CharData> {
+    let stroke_ranges = char_data.stroke_ranges();
+    let mut res: Vec<CharData> = Vec::new();
+    for window_length in stroke_count..stroke_count + MAX_COMPONENT_GAP + 1 {
+        if window_length > stroke_ranges.len() {
+            break;
+        }
+        for first in 0..stroke_ranges.len() - window_length + 1 {
+            let strokes: Vec<&[SubStrokeTriple]> = (first..first + window_length).map(|i| char_data.stroke(i)).collect();
+            // A run of a template's strokes always fits in a template
+            res.extend(CharData::from_strokes(char_data.hanzi, renormalize(&strokes)).ok());
+        }
+    }
+    res
+}
+
+// Scales and moves the substrokes so that their bounding box, padded to a square, fills the 16 x 16 grid
+fn renormalize(strokes: &[&[SubStrokeTriple]]) -> Vec<Vec<SubStrokeTriple>> {
+    let (mut left, mut top, mut right, mut bottom) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    for sub_stroke in strokes.iter().flat_map(|stroke| stroke.iter()) {
+        let (start, end) = get_sub_stroke_ends(sub_stroke);
+        left = left.min(start.0).min(end.0);
+        right = right.max(start.0).max(end.0);
+        top = top.min(start.1).min(end.1);
+        bottom = bottom.max(start.1).max(end.1);
+    }
+    // A lone dot still needs some extent
+    let side = f32::max(f32::max(right - left, bottom - top), 1f32);
+    let pad_x = (side - (right - left)) / 2f32;
+    let pad_y = (side - (bottom - top)) / 2f32;
+    let scale = 15f32 / side;
+    let to_grid = |value: f32| (value * scale).round().clamp(0f32, 15f32) as u8;
+    strokes.iter().map(|stroke| stroke.iter().map(|sub_stroke| {
+        let center_x = (sub_stroke.center >> 4) as f32 - left + pad_x;
+        let center_y = (sub_stroke.center & 0x0f) as f32 - top + pad_y;
+        SubStrokeTriple {
+            dir: sub_stroke.dir,
+            length: f32::min((sub_stroke.length as f32 * scale).round(), 255f32) as u8,
+            center: (to_grid(center_x) << 4) | to_grid(center_y),
+        }
+    }).collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        // Two horizontal strokes stacked in the top half, and a third stroke below
+        let stroke = |center: u8| vec![SubStrokeTriple { dir: 0, length: 102, center: center }];
+        let char_data = CharData::from_strokes('三', vec![stroke(0x72), stroke(0x75), stroke(0x7d)]).unwrap();
+        let windows = get_component_windows(&char_data, 2);
+        // Strokes 0-1 and 1-2, then 0-2 with the middle stroke as a gap
+        assert_eq!(windows.len(), 3);
+        assert!(windows.iter().all(|window| window.hanzi == '三'));
+        assert_eq!(windows[2].stroke_count, 3);
+        // The first two strokes fill the grid's width, and are centered vertically
+        let first = &windows[0].sub_strokes;
+        assert_eq!(first[0].length, 255);
+        assert_eq!(first[0].center & 0x0f, 4);
+        assert_eq!(first[1].center & 0x0f, 11);
+        assert!(get_component_windows(&char_data, 4).is_empty());
+    }
+}
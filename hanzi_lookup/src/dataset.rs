@@ -0,0 +1,639 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::*;
+
+// Container layout, all integers little-endian:
+//   magic (4 bytes) | format version (u16) | bincode DatasetHeader | bincode Vec<CharData> payload
+// The header records the payload's length and CRC-32, so truncated or corrupted files are caught before decoding.
+// Version 2 added each stroke's substroke count to CharData, version 3 its tag, and version 4 its region. Older files still load:
+// version 1 stroke boundaries are inferred, templates from before version 3 are all standard ones, and from before version 4, used everywhere.
+pub const DATASET_MAGIC: [u8; 4] = *b"HZLK";
+pub const DATASET_FORMAT_VERSION: u16 = 4;
+const DATASET_FORMAT_VERSION_1: u16 = 1;
+const DATASET_FORMAT_VERSION_2: u16 = 2;
+const DATASET_FORMAT_VERSION_3: u16 = 3;
+
+// Widest gap between consecutive substrokes of a stroke: rounding centers to the grid moves each end
+// by up to half a unit across and down, so one's end and the next one's start can be a unit apart on both axes
+const MAX_JOIN_GAP: f32 = std::f32::consts::SQRT_2;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct SubStrokeTriple {
+    pub dir: u8,
+    pub length: u8,
+    pub center: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CharData {
+    pub hanzi: char,
+    pub stroke_count: u16,
+    pub sub_strokes: Vec<SubStrokeTriple>,
+    // Number of substrokes in each stroke, in writing order; adds up to the length of sub_strokes
+    pub sub_strokes_per_stroke: Vec<u8>,
+    // A character may have several templates. None is its standard one; others say what sets them apart,
+    // such as "alt-order" for a common non-standard stroke order.
+    pub tag: Option<String>,
+    // Locale whose form of the character this is, such as "TW" or "JP"; None for the form used everywhere else
+    pub region: Option<String>,
+}
+
+// CharData as format version 1 stored it, without stroke boundaries
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct CharDataV1 {
+    hanzi: char,
+    stroke_count: u16,
+    sub_strokes: Vec<SubStrokeTriple>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct CharDataV2 {
+    hanzi: char,
+    stroke_count: u16,
+    sub_strokes: Vec<SubStrokeTriple>,
+    sub_strokes_per_stroke: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct CharDataV3 {
+    hanzi: char,
+    stroke_count: u16,
+    sub_strokes: Vec<SubStrokeTriple>,
+    sub_strokes_per_stroke: Vec<u8>,
+    tag: Option<String>,
+}
+
+impl CharData {
+    // Template from its strokes, each given as its substrokes.
+    // Fails if a stroke has more substrokes, or the character more strokes, than the format can count.
+    pub fn from_strokes(hanzi: char, strokes: Vec<Vec<SubStrokeTriple>>) -> Result<CharData, DatasetError> {
+        let stroke_count = u16::try_from(strokes.len())
+            .map_err(|_| DatasetError::Corrupt(format!("{} has {} strokes, more than a template can have.", hanzi, strokes.len())))?;
+        let sub_strokes_per_stroke = strokes.iter().map(|stroke| u8::try_from(stroke.len())
+            .map_err(|_| DatasetError::Corrupt(format!("{} has a stroke of {} substrokes, more than a stroke can have.", hanzi, stroke.len()))))
+            .collect::<Result<Vec<u8>, DatasetError>>()?;
+        Ok(CharData {
+            hanzi,
+            stroke_count,
+            sub_strokes: strokes.into_iter().flatten().collect(),
+            sub_strokes_per_stroke,
+            tag: None,
+            region: None,
+        })
+    }
+
+    // For sources that only know how many strokes there are: boundaries are put where one substroke ends
+    // farthest from where the next one starts, since within a stroke, substrokes follow on from each other.
+    // Fails if there are fewer substrokes than strokes, as every stroke has at least one: the strokes can't all be found.
+    pub fn with_inferred_strokes(hanzi: char, stroke_count: u16, sub_strokes: Vec<SubStrokeTriple>) -> Result<CharData, DatasetError> {
+        if stroke_count as usize > sub_strokes.len() || (stroke_count == 0 && !sub_strokes.is_empty()) {
+            return Err(DatasetError::Corrupt(format!("{} has {} strokes but {} substrokes, so its strokes can't be told apart.", hanzi, stroke_count, sub_strokes.len())));
+        }
+        let sub_strokes_per_stroke = infer_sub_strokes_per_stroke(stroke_count as usize, &sub_strokes);
+        Ok(CharData {
+            hanzi,
+            stroke_count,
+            sub_strokes,
+            sub_strokes_per_stroke,
+            tag: None,
+            region: None,
+        })
+    }
+
+    // Variant with the same strokes written in a different order: order lists this template's stroke indexes in the new order.
+    // None unless order has each stroke exactly once.
+    pub fn reordered(&self, order: &[usize], tag: &str) -> Option<CharData> {
+        let mut sorted = order.to_vec();
+        sorted.sort();
+        if sorted != (0..self.sub_strokes_per_stroke.len()).collect::<Vec<usize>>() {
+            return None;
+        }
+        let strokes: Vec<Vec<SubStrokeTriple>> = order.iter().map(|&index| self.stroke(index).to_vec()).collect();
+        let mut res = CharData::from_strokes(self.hanzi, strokes).ok()?;
+        res.stroke_count = self.stroke_count;
+        res.tag = Some(tag.to_string());
+        res.region = self.region.clone();
+        Some(res)
+    }
+
+    // Whether each stroke's substrokes follow on from each other, as far as centers rounded to the grid can tell.
+    // An inferred grouping that joins two strokes leaves the gap between them inside a stroke, which is usually wider.
+    pub fn strokes_join_up(&self) -> bool {
+        self.stroke_ranges().iter().all(|&(start, end)| {
+            (start + 1..end).all(|i| get_join_gap(&self.sub_strokes[i - 1], &self.sub_strokes[i]) <= MAX_JOIN_GAP)
+        })
+    }
+
+    // Start and end of each stroke's range in sub_strokes
+    pub fn stroke_ranges(&self) -> Vec<(usize, usize)> {
+        let mut res: Vec<(usize, usize)> = Vec::with_capacity(self.sub_strokes_per_stroke.len());
+        let mut start = 0;
+        for count in &self.sub_strokes_per_stroke {
+            res.push((start, start + *count as usize));
+            start += *count as usize;
+        }
+        res
+    }
+
+    // The substrokes of one stroke
+    pub fn stroke(&self, index: usize) -> &[SubStrokeTriple] {
+        let (start, end) = self.stroke_ranges()[index];
+        &self.sub_strokes[start..end]
+    }
+}
+
+// Picks the stroke_count - 1 widest gaps, so there are always exactly stroke_count strokes; needs 1 <= stroke_count <= substrokes
+fn infer_sub_strokes_per_stroke(stroke_count: usize, sub_strokes: &[SubStrokeTriple]) -> Vec<u8> {
+    let sub_stroke_count = sub_strokes.len();
+    if sub_stroke_count == 0 {
+        return Vec::new();
+    }
+    let mut gaps: Vec<(f32, usize)> = Vec::with_capacity(sub_stroke_count - 1);
+    for i in 1..sub_stroke_count {
+        gaps.push((get_join_gap(&sub_strokes[i - 1], &sub_strokes[i]), i));
+    }
+    gaps.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut starts: Vec<usize> = gaps.iter().take(stroke_count - 1).map(|gap| gap.1).collect();
+    starts.sort();
+    starts.push(sub_stroke_count);
+    let mut res: Vec<u8> = Vec::with_capacity(stroke_count);
+    let mut prev_start = 0;
+    for start in starts {
+        res.push((start - prev_start) as u8);
+        prev_start = start;
+    }
+    res
+}
+
+// Distance from the end of one substroke to the start of the next, on the 16 x 16 grid of centers
+fn get_join_gap(prev: &SubStrokeTriple, next: &SubStrokeTriple) -> f32 {
+    let (_, prev_end) = get_sub_stroke_ends(prev);
+    let (start, _) = get_sub_stroke_ends(next);
+    let dx = start.0 - prev_end.0;
+    let dy = start.1 - prev_end.1;
+    (dx * dx + dy * dy).sqrt()
+}
+
+// Start and end point of a template substroke, on the 16 x 16 grid of centers
+pub(crate) fn get_sub_stroke_ends(sub_stroke: &SubStrokeTriple) -> ((f32, f32), (f32, f32)) {
+    let center_x = (sub_stroke.center >> 4) as f32;
+    let center_y = (sub_stroke.center & 0x0f) as f32;
+    // Template lengths are in 255ths of the grid's 15 unit side: that scale makes substrokes within a stroke
+    // meet end to start most closely (gaps under 1 unit, while gaps between strokes are mostly over 1.8)
+    let half_length = sub_stroke.length as f32 / 255f32 * 15f32 / 2f32;
+    // Direction 0 is to the right and 64 is up, but Y grows downwards
+    let angle = sub_stroke.dir as f32 * std::f32::consts::PI * 2f32 / 256f32;
+    let dx = angle.cos() * half_length;
+    let dy = -angle.sin() * half_length;
+    ((center_x - dx, center_y - dy), (center_x + dx, center_y + dy))
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct DatasetHeader {
+    name: String,
+    source: String,
+    char_count: u32,
+    payload_length: u64,
+    checksum: u32,
+}
+
+// Why a dataset file could not be loaded
+#[derive(Debug)]
+pub enum DatasetError {
+    Io(std::io::Error),
+    // File doesn't start with DATASET_MAGIC; e.g., a bare pre-versioning mmah.bin
+    BadMagic,
+    UnsupportedVersion(u16),
+    // File ends before the header or the payload is complete
+    Truncated,
+    ChecksumMismatch { expected: u32, actual: u32 },
+    // Checksum is fine but the content doesn't decode, or disagrees with the header
+    Corrupt(String),
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatasetError::Io(err) => write!(f, "Failed to read dataset: {}", err),
+            DatasetError::BadMagic => write!(f, "Not a hanzi_lookup dataset file."),
+            DatasetError::UnsupportedVersion(version) => write!(f, "Unsupported dataset format version {}; expected {}..={}.", version, DATASET_FORMAT_VERSION_1, DATASET_FORMAT_VERSION),
+            DatasetError::Truncated => write!(f, "Dataset file is truncated."),
+            DatasetError::ChecksumMismatch { expected, actual } => write!(f, "Dataset checksum mismatch: expected {:08x}, got {:08x}.", expected, actual),
+            DatasetError::Corrupt(msg) => write!(f, "Dataset file is corrupt: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+impl From<std::io::Error> for DatasetError {
+    fn from(err: std::io::Error) -> DatasetError {
+        DatasetError::Io(err)
+    }
+}
+
+// Running out of input mid-structure means truncation; anything else bincode rejects is corruption
+fn decode_error(err: bincode::Error) -> DatasetError {
+    match *err {
+        bincode::ErrorKind::Io(ref io_err) if io_err.kind() == std::io::ErrorKind::UnexpectedEof => DatasetError::Truncated,
+        _ => DatasetError::Corrupt(err.to_string()),
+    }
+}
+
+// The character templates a recognizer matches input against
+pub struct Dataset {
+    name: String,
+    source: String,
+    pub(crate) chars: Vec<CharData>,
+}
+
+impl Dataset {
+    // Builds a dataset from custom templates; source is free text for provenance and license
+    pub fn new(name: &str, source: &str, chars: Vec<CharData>) -> Dataset {
+        Dataset {
+            name: name.to_string(),
+            source: source.to_string(),
+            chars,
+        }
+    }
+
+    // The Make Me a Hanzi derived data compiled into the library
+    #[cfg(feature = "embedded-data")]
+    pub fn embedded() -> Dataset {
+        let hwbytes = include_bytes!("../data/mmah.bin");
+        Dataset::from_bytes(&hwbytes[..]).expect("Failed to deserialize.")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Dataset, DatasetError> {
+        // Fixed part: magic and version
+        if bytes.len() < 6 {
+            return Err(DatasetError::Truncated);
+        }
+        if bytes[0..4] != DATASET_MAGIC {
+            return Err(DatasetError::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if !(DATASET_FORMAT_VERSION_1..=DATASET_FORMAT_VERSION).contains(&version) {
+            return Err(DatasetError::UnsupportedVersion(version));
+        }
+        // Header; its serialized size tells us where the payload starts
+        let rest = &bytes[6..];
+        let header: DatasetHeader = bincode::deserialize(rest).map_err(decode_error)?;
+        let header_length = bincode::serialized_size(&header).map_err(decode_error)? as usize;
+        let payload = &rest[header_length..];
+        if (payload.len() as u64) < header.payload_length {
+            return Err(DatasetError::Truncated);
+        }
+        if (payload.len() as u64) > header.payload_length {
+            return Err(DatasetError::Corrupt("Trailing data after payload.".to_string()));
+        }
+        let checksum = crc32(payload);
+        if checksum != header.checksum {
+            return Err(DatasetError::ChecksumMismatch { expected: header.checksum, actual: checksum });
+        }
+        let chars: Vec<CharData> = match version {
+            DATASET_FORMAT_VERSION_1 => {
+                let chars: Vec<CharDataV1> = bincode::deserialize(payload).map_err(decode_error)?;
+                chars.into_iter().map(|c| CharData::with_inferred_strokes(c.hanzi, c.stroke_count, c.sub_strokes)).collect::<Result<Vec<CharData>, DatasetError>>()?
+            }
+            DATASET_FORMAT_VERSION_2 => {
+                let chars: Vec<CharDataV2> = bincode::deserialize(payload).map_err(decode_error)?;
+                chars.into_iter().map(|c| CharData {
+                    hanzi: c.hanzi,
+                    stroke_count: c.stroke_count,
+                    sub_strokes: c.sub_strokes,
+                    sub_strokes_per_stroke: c.sub_strokes_per_stroke,
+                    tag: None,
+                    region: None,
+                }).collect()
+            }
+            DATASET_FORMAT_VERSION_3 => {
+                let chars: Vec<CharDataV3> = bincode::deserialize(payload).map_err(decode_error)?;
+                chars.into_iter().map(|c| CharData {
+                    hanzi: c.hanzi,
+                    stroke_count: c.stroke_count,
+                    sub_strokes: c.sub_strokes,
+                    sub_strokes_per_stroke: c.sub_strokes_per_stroke,
+                    tag: c.tag,
+                    region: None,
+                }).collect()
+            }
+            _ => bincode::deserialize(payload).map_err(decode_error)?,
+        };
+        if chars.len() != header.char_count as usize {
+            return Err(DatasetError::Corrupt(format!("Header declares {} characters, payload has {}.", header.char_count, chars.len())));
+        }
+        for char_data in &chars {
+            let total: usize = char_data.sub_strokes_per_stroke.iter().map(|count| *count as usize).sum();
+            if total != char_data.sub_strokes.len() {
+                return Err(DatasetError::Corrupt(format!("Strokes of {} add up to {} substrokes instead of {}.", char_data.hanzi, total, char_data.sub_strokes.len())));
+            }
+            // Stroke-level features index strokes by the grouping, so it must have every stroke
+            if char_data.sub_strokes_per_stroke.len() != char_data.stroke_count as usize {
+                return Err(DatasetError::Corrupt(format!("{} has {} strokes, but its substrokes are grouped into {}.", char_data.hanzi, char_data.stroke_count, char_data.sub_strokes_per_stroke.len())));
+            }
+        }
+        Ok(Dataset {
+            name: header.name,
+            source: header.source,
+            chars,
+        })
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Dataset, DatasetError> {
+        // Checksum covers the whole payload, so we need it all in memory anyway
+        let mut bytes: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Dataset::from_bytes(&bytes)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Dataset, DatasetError> {
+        let file = File::open(path)?;
+        Dataset::from_reader(file)
+    }
+
+    // Serializes dataset in the versioned container format that from_bytes reads
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let payload = bincode::serialize(&self.chars).expect("Failed to serialize.");
+        let header = DatasetHeader {
+            name: self.name.clone(),
+            source: self.source.clone(),
+            char_count: self.chars.len() as u32,
+            payload_length: payload.len() as u64,
+            checksum: crc32(&payload),
+        };
+        let mut res: Vec<u8> = Vec::with_capacity(payload.len() + 256);
+        res.extend_from_slice(&DATASET_MAGIC);
+        res.extend_from_slice(&DATASET_FORMAT_VERSION.to_le_bytes());
+        res.extend(bincode::serialize(&header).expect("Failed to serialize."));
+        res.extend(payload);
+        res
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), DatasetError> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Provenance and license of the data
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // A character's templates, its standard one first
+    pub fn templates(&self, hanzi: char) -> Vec<&CharData> {
+        let mut res: Vec<&CharData> = self.chars.iter().filter(|char_data| char_data.hanzi == hanzi).collect();
+        res.sort_by_key(|char_data| char_data.tag.is_some());
+        res
+    }
+
+    // Number of character templates in the dataset; a character may have more than one
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+}
+
+// Standard CRC-32 (IEEE, reflected), bit by bit; only runs once per load, so no table
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MMAH_BIN: &[u8] = include_bytes!("../data/mmah.bin");
+
+    fn tiny_dataset() -> Dataset {
+        let chars = vec![CharData::from_strokes('一', vec![vec![SubStrokeTriple { dir: 0, length: 180, center: 0x87 }]]).unwrap()];
+        Dataset::new("tiny", "Test data", chars)
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let dataset = Dataset::from_bytes(MMAH_BIN).unwrap();
+        assert_eq!(dataset.len(), 9510);
+        assert_eq!(dataset.name(), "mmah");
+        assert!(dataset.source().contains("Arphic Public License"));
+        assert!(dataset.chars[0].hanzi == '丿');
+        assert_eq!(dataset.chars[0].stroke_count, 1);
+        assert_eq!(dataset.chars[0].sub_strokes.len(), 2);
+    }
+
+    #[test]
+    fn test_from_file() {
+        let dataset = Dataset::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/data/mmah.bin")).unwrap();
+        assert_eq!(dataset.len(), 9510);
+        match Dataset::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/data/missing.bin")) {
+            Err(DatasetError::Io(_)) => (),
+            _ => panic!("Expected an I/O error."),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dataset = tiny_dataset();
+        let mut bytes: Vec<u8> = Vec::new();
+        dataset.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, dataset.to_bytes());
+        let loaded = Dataset::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.name(), "tiny");
+        assert_eq!(loaded.source(), "Test data");
+        assert_eq!(loaded.chars, dataset.chars);
+    }
+
+    #[test]
+    fn test_strokes() {
+        let dataset = Dataset::from_bytes(MMAH_BIN).unwrap();
+        let find = |hanzi: char| dataset.chars.iter().find(|char_data| char_data.hanzi == hanzi).unwrap();
+        // 十's vertical starts with a short tick
+        assert_eq!(find('十').sub_strokes_per_stroke, [1, 2]);
+        assert_eq!(find('十').stroke_ranges(), [(0, 1), (1, 3)]);
+        assert_eq!(find('十').stroke(1).len(), 2);
+        assert_eq!(find('一').stroke_ranges(), [(0, 1)]);
+        for char_data in &dataset.chars {
+            assert_eq!(char_data.sub_strokes_per_stroke.len(), char_data.stroke_count as usize);
+            assert!(char_data.sub_strokes_per_stroke.iter().all(|count| *count > 0));
+        }
+        // The alternate stroke orders reorder these; checked stroke by stroke against the substrokes' geometry
+        assert_eq!(find('火').sub_strokes_per_stroke, [1, 2, 3, 1]);
+        assert_eq!(find('方').sub_strokes_per_stroke, [1, 1, 3, 2]);
+        assert_eq!(find('必').sub_strokes_per_stroke, [1, 3, 1, 2, 1]);
+        // Boundaries are where substrokes don't join up
+        let joined = vec![SubStrokeTriple { dir: 0, length: 68, center: 0x27 }, SubStrokeTriple { dir: 0, length: 68, center: 0x67 }];
+        assert_eq!(CharData::with_inferred_strokes('x', 1, joined.clone()).unwrap().sub_strokes_per_stroke, [2]);
+        let apart = vec![SubStrokeTriple { dir: 0, length: 68, center: 0x27 }, SubStrokeTriple { dir: 0, length: 68, center: 0x2c }];
+        assert_eq!(CharData::with_inferred_strokes('x', 2, apart.clone()).unwrap().sub_strokes_per_stroke, [1, 1]);
+        assert!(CharData::with_inferred_strokes('x', 2, apart.clone()).unwrap().strokes_join_up());
+        assert!(!CharData::from_strokes('x', vec![apart]).unwrap().strokes_join_up());
+        // Nearly all of the embedded templates' inferred strokes join up, as the converter checks
+        let joining = dataset.chars.iter().filter(|char_data| char_data.strokes_join_up()).count();
+        assert!(joining as f32 >= 0.99 * dataset.chars.len() as f32);
+        // More strokes than substrokes can't be grouped
+        assert!(CharData::with_inferred_strokes('x', 3, joined).is_err());
+    }
+
+    // Container around a payload of an older format version
+    fn legacy_bytes<T: serde::Serialize>(version: u16, chars: &[T]) -> Vec<u8> {
+        let payload = bincode::serialize(chars).unwrap();
+        let header = DatasetHeader {
+            name: "mmah".to_string(),
+            source: format!("Version {}", version),
+            char_count: chars.len() as u32,
+            payload_length: payload.len() as u64,
+            checksum: crc32(&payload),
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&DATASET_MAGIC);
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend(bincode::serialize(&header).unwrap());
+        bytes.extend(payload);
+        bytes
+    }
+
+    fn standard_chars() -> Vec<CharData> {
+        Dataset::from_bytes(MMAH_BIN).unwrap().chars.into_iter().filter(|c| c.tag.is_none()).collect()
+    }
+
+    #[test]
+    fn test_version_1() {
+        // No stroke boundaries: they are inferred just as when the current file was built
+        let chars: Vec<CharDataV1> = standard_chars().into_iter()
+            .map(|c| CharDataV1 { hanzi: c.hanzi, stroke_count: c.stroke_count, sub_strokes: c.sub_strokes })
+            .collect();
+        let loaded = Dataset::from_bytes(&legacy_bytes(DATASET_FORMAT_VERSION_1, &chars)).unwrap();
+        assert_eq!(loaded.chars, standard_chars());
+    }
+
+    #[test]
+    fn test_version_2() {
+        // No tags: every template is a standard one
+        let chars: Vec<CharDataV2> = standard_chars().into_iter()
+            .map(|c| CharDataV2 { hanzi: c.hanzi, stroke_count: c.stroke_count, sub_strokes: c.sub_strokes, sub_strokes_per_stroke: c.sub_strokes_per_stroke })
+            .collect();
+        let loaded = Dataset::from_bytes(&legacy_bytes(DATASET_FORMAT_VERSION_2, &chars)).unwrap();
+        assert_eq!(loaded.chars, standard_chars());
+        // A grouping that misses strokes is rejected
+        let mut chars = chars;
+        chars[0].stroke_count += 1;
+        match Dataset::from_bytes(&legacy_bytes(DATASET_FORMAT_VERSION_2, &chars)) {
+            Err(DatasetError::Corrupt(_)) => (),
+            _ => panic!("Expected a corrupt dataset."),
+        }
+    }
+
+    #[test]
+    fn test_version_3() {
+        // No regions: every template is used everywhere
+        let chars: Vec<CharDataV3> = Dataset::from_bytes(MMAH_BIN).unwrap().chars.into_iter()
+            .map(|c| CharDataV3 { hanzi: c.hanzi, stroke_count: c.stroke_count, sub_strokes: c.sub_strokes, sub_strokes_per_stroke: c.sub_strokes_per_stroke, tag: c.tag })
+            .collect();
+        let loaded = Dataset::from_bytes(&legacy_bytes(DATASET_FORMAT_VERSION_3, &chars)).unwrap();
+        assert_eq!(loaded.chars, Dataset::from_bytes(MMAH_BIN).unwrap().chars);
+    }
+
+    #[test]
+    fn test_variants() {
+        let dataset = Dataset::from_bytes(MMAH_BIN).unwrap();
+        // 火 is often written 人 first, then the two dots
+        let templates = dataset.templates('火');
+        assert_eq!(templates.len(), 2);
+        assert_eq!(templates[0].tag, None);
+        assert_eq!(templates[1].tag, Some("alt-order".to_string()));
+        assert_eq!(templates[1].stroke(0), templates[0].stroke(2));
+        assert_eq!(templates[1].stroke(2), templates[0].stroke(0));
+        assert_eq!(templates[1].stroke_count, templates[0].stroke_count);
+        assert_eq!(dataset.templates('一').len(), 1);
+        assert_eq!(dataset.templates('x').len(), 0);
+
+        let mut dataset = tiny_dataset();
+        let two_strokes = CharData::from_strokes('二', vec![
+            vec![SubStrokeTriple { dir: 0, length: 100, center: 0x74 }],
+            vec![SubStrokeTriple { dir: 0, length: 180, center: 0x7b }],
+        ]).unwrap();
+        assert!(two_strokes.reordered(&[0, 0], "alt-order").is_none());
+        assert!(two_strokes.reordered(&[1], "alt-order").is_none());
+        let reordered = two_strokes.reordered(&[1, 0], "alt-order").unwrap();
+        assert_eq!(reordered.sub_strokes_per_stroke, [1, 1]);
+        assert_eq!(reordered.sub_strokes[0], two_strokes.sub_strokes[1]);
+        // Tags and regions survive a round trip
+        let mut regional = two_strokes.clone();
+        regional.region = Some("TW".to_string());
+        dataset.chars.push(two_strokes);
+        dataset.chars.push(reordered);
+        dataset.chars.push(regional);
+        assert_eq!(Dataset::from_bytes(&dataset.to_bytes()).unwrap().chars, dataset.chars);
+    }
+
+    #[test]
+    fn test_bad_strokes() {
+        let mut dataset = tiny_dataset();
+        dataset.chars[0].sub_strokes_per_stroke = vec![2];
+        assert!(match Dataset::from_bytes(&dataset.to_bytes()) { Err(DatasetError::Corrupt(_)) => true, _ => false });
+        // A stroke's substroke count has to fit in a byte
+        let long_stroke = vec![SubStrokeTriple { dir: 0, length: 10, center: 0x77 }; 256];
+        assert!(match CharData::from_strokes('一', vec![long_stroke]) { Err(DatasetError::Corrupt(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(match Dataset::from_bytes(&MMAH_BIN[..3]) { Err(DatasetError::Truncated) => true, _ => false });
+        assert!(match Dataset::from_bytes(&MMAH_BIN[..20]) { Err(DatasetError::Truncated) => true, _ => false });
+        assert!(match Dataset::from_bytes(&MMAH_BIN[..1000]) { Err(DatasetError::Truncated) => true, _ => false });
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        // Pre-versioning files were a bare bincode Vec<CharData>
+        let legacy = bincode::serialize(&tiny_dataset().chars).unwrap();
+        assert!(match Dataset::from_bytes(&legacy) { Err(DatasetError::BadMagic) => true, _ => false });
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let mut bytes = tiny_dataset().to_bytes();
+        bytes[4] = 5;
+        assert!(match Dataset::from_bytes(&bytes) { Err(DatasetError::UnsupportedVersion(5)) => true, _ => false });
+        bytes[4] = 0;
+        assert!(match Dataset::from_bytes(&bytes) { Err(DatasetError::UnsupportedVersion(0)) => true, _ => false });
+        // The message gives every version this build reads
+        assert_eq!(DatasetError::UnsupportedVersion(5).to_string(), "Unsupported dataset format version 5; expected 1..=4.");
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut bytes = tiny_dataset().to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert!(match Dataset::from_bytes(&bytes) { Err(DatasetError::ChecksumMismatch { .. }) => true, _ => false });
+        let mut bytes = tiny_dataset().to_bytes();
+        bytes.push(0);
+        assert!(match Dataset::from_bytes(&bytes) { Err(DatasetError::Corrupt(_)) => true, _ => false });
+    }
+}
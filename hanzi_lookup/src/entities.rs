@@ -1,15 +1,21 @@
 // One analyzed stroke
-pub struct AnalyzedStroke<'a> {
+pub struct AnalyzedStroke<'a, P: 'a = super::Point> {
+    // Position of the stroke in the input; slips are left out of the analysis, so this can differ from its position here
+    pub index: usize,
     // The stroke's points
-    pub points: &'a Vec<super::Point>,
+    pub points: &'a [P],
     // Indexes of pivot points delimiting substrokes
     pub pivot_indexes: Vec<usize>,
     // The substrokes delineated by the identified pivot points
     pub sub_strokes: Vec<SubStroke>,
+    // Milliseconds from first to last point, if every point has a timestamp
+    pub duration: Option<f64>,
+    // Mean pressure over the points that report it
+    pub pressure: Option<f32>,
 }
 
 // A single analyzed substroke
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SubStroke {
     // The substroke's direction; normalized into 0..256 from 0..2*PI
     pub direction: f32,
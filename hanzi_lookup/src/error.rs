@@ -0,0 +1,69 @@
+use std::fmt;
+
+use super::dataset::DatasetError;
+
+// Everything that can go wrong in the public API
+#[derive(Debug)]
+pub enum Error {
+    // Loading a dataset failed
+    Dataset(DatasetError),
+    // Library was built without embedded data, and no dataset has been loaded
+    NoDataset,
+    // Maximum number of matches must be positive
+    ZeroLimit,
+    // Input has no strokes at all
+    NoStrokes,
+    // A stroke needs at least two points to have a direction; stroke is the stroke's index
+    TooFewPoints { stroke: usize },
+    // Coordinates must be finite numbers; stroke and point are indexes
+    InvalidCoordinate { stroke: usize, point: usize },
+    // Timestamp or pressure is present but not a finite number; stroke and point are indexes
+    InvalidPenData { stroke: usize, point: usize },
+    // Target character is not in the dataset
+    UnknownCharacter(char),
+    // Lookup options are out of range
+    InvalidOptions(String),
+    // A frequency table line is not a character, a tab and a count; line is 1-based
+    InvalidFrequencyTable { line: usize },
+    // An n-gram table line is not two or three characters, a tab and a count; line is 1-based
+    InvalidNgramTable { line: usize },
+    // Input could not be interpreted as an array of strokes
+    InvalidInput(String),
+    // Result could not be converted to JSON
+    Serialization(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Dataset(err) => write!(f, "{}", err),
+            Error::NoDataset => write!(f, "No dataset loaded; built without embedded data."),
+            Error::ZeroLimit => write!(f, "Expected a positive number for the maximum number of matches."),
+            Error::NoStrokes => write!(f, "Input contains no strokes."),
+            Error::TooFewPoints { stroke } => write!(f, "Stroke {} has fewer than two points.", stroke),
+            Error::InvalidCoordinate { stroke, point } => write!(f, "Point {} of stroke {} has a coordinate that is not a finite number.", point, stroke),
+            Error::InvalidPenData { stroke, point } => write!(f, "Point {} of stroke {} has a timestamp or pressure that is not a finite number.", point, stroke),
+            Error::UnknownCharacter(hanzi) => write!(f, "Character {} is not in the dataset.", hanzi),
+            Error::InvalidOptions(msg) => write!(f, "Invalid lookup options: {}", msg),
+            Error::InvalidFrequencyTable { line } => write!(f, "Line {} of the frequency table is not a character, a tab and a count.", line),
+            Error::InvalidNgramTable { line } => write!(f, "Line {} of the n-gram table is not two or three characters, a tab and a count.", line),
+            Error::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            Error::Serialization(msg) => write!(f, "Failed to serialize result: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Dataset(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<DatasetError> for Error {
+    fn from(err: DatasetError) -> Error {
+        Error::Dataset(err)
+    }
+}
@@ -0,0 +1,39 @@
+use serde_derive::{Deserialize, Serialize};
+
+// Why a character got its score: the DP alignment between the input's substrokes and the character's template
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Explanation {
+    pub hanzi: char,
+    // Tag of the template the alignment is against; None for the character's standard template
+    pub tag: Option<String>,
+    // Region of that template; None for the form used everywhere
+    pub region: Option<String>,
+    // Same as the score lookup reports for this character
+    pub score: f32,
+    // Index of the input stroke each input substroke belongs to
+    pub input_strokes: Vec<usize>,
+    // From the first substrokes to the last
+    pub steps: Vec<AlignmentStep>,
+}
+
+// One move along the alignment; input and template are substroke indexes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AlignmentStep {
+    Matched { input: usize, template: usize, scores: SubStrokeScores },
+    // Input substroke with no counterpart in the template; penalty is what skipping it cost
+    SkippedInput { input: usize, penalty: f32 },
+    // Template substroke the input doesn't have
+    SkippedTemplate { template: usize, penalty: f32 },
+}
+
+// How one input substroke compares to one template substroke
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SubStrokeScores {
+    // Includes the bonus that makes direction matter less for short, dot-like substrokes
+    pub direction: f32,
+    pub length: f32,
+    // 1 if the centers coincide, dropping towards 0 as they move apart
+    pub position: f32,
+    // What the pair adds to the character's score
+    pub total: f32,
+}
@@ -0,0 +1,307 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::analyzed_character::*;
+use super::dataset::*;
+use super::entities::*;
+use super::matcher::*;
+use super::*;
+
+// Input and template strokes less similar than this are not paired up
+const MIN_STROKE_SIMILARITY: f32 = 0.3;
+// A paired stroke whose center is less close to the template's than this is in the wrong place
+pub(crate) const MIN_POSITION_SCORE: f32 = 0.8;
+// A stroke only counts as reversed if it fits the template this much better backwards than forwards
+const REVERSED_MARGIN: f32 = 0.1;
+// When pairing, similarity counts for this much less per unit of difference in relative position in the writing order.
+// Hand-drawn strokes often resemble a similar stroke elsewhere in the character a little better than their own;
+// this tips such cases towards the expected stroke, while a stroke clearly written out of order still pairs with its true match.
+const ORDER_PRIOR_WEIGHT: f32 = 0.3;
+// Share of a stroke's similarity lost for each of its faults in the overall score
+const FAULT_PENALTY: f32 = 0.25;
+
+// How well the input reproduces one given character
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Grade {
+    pub hanzi: char,
+    // Tag of the template graded against; None for the character's standard template
+    pub tag: Option<String>,
+    // Region of that template; None for the form used everywhere
+    pub region: Option<String>,
+    // 0 to 1: similarity of the paired strokes, less their faults, over the input's or the template's stroke count, whichever is larger
+    pub score: f32,
+    // Input strokes in the order they were written, then the template strokes the input doesn't have
+    pub strokes: Vec<StrokeGrade>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StrokeGrade {
+    // Index of the input stroke; None for a missing stroke
+    pub input: Option<usize>,
+    // Index of the template stroke it was paired with; None for an extra stroke
+    pub template: Option<usize>,
+    // Shape and position together, about 0 to 1; 0 if unpaired
+    pub similarity: f32,
+    // Empty if the stroke is correct
+    pub faults: Vec<StrokeFault>,
+}
+
+impl StrokeGrade {
+    pub fn is_correct(&self) -> bool {
+        self.faults.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum StrokeFault {
+    // Written after a stroke that should come later
+    WrongOrder,
+    // Written from end to start
+    Reversed,
+    // Right shape, but too far from where it belongs
+    WrongPosition,
+    // Template stroke the input doesn't have
+    Missing,
+    // Input stroke that doesn't belong to the character
+    Extra,
+    // Not enough like the stroke that was expected; only reported when tracing
+    WrongShape,
+}
+
+// An input stroke that could stand for a template stroke; input is the position among the analyzed strokes
+#[derive(Clone, Copy)]
+struct Pairing {
+    input: usize,
+    template: usize,
+    similarity: f32,
+    reversed: bool,
+    // Contribution adjusted by the order prior; pairs are made in decreasing order of this
+    rank: f32,
+}
+
+// Grades the input against one template, stroke by stroke
+pub(crate) fn grade_char<P: InputPoint>(matcher: &Matcher, input_char: &AnalyzedCharacter<P>, char_data: &CharData) -> Grade {
+    let templates: Vec<&[SubStrokeTriple]> = char_data.stroke_ranges().into_iter()
+        .map(|(start, end)| &char_data.sub_strokes[start..end])
+        .collect();
+    let input_strokes = &input_char.analyzed_strokes;
+
+    // Every input stroke against every template stroke, forwards and backwards
+    let mut candidates: Vec<Pairing> = Vec::new();
+    for (i, stroke) in input_strokes.iter().enumerate() {
+        let backwards = reverse_sub_strokes(&stroke.sub_strokes);
+        for (t, template) in templates.iter().enumerate() {
+            let (similarity, reversed) = compare_both_ways(matcher, &stroke.sub_strokes, &backwards, template);
+            if similarity >= MIN_STROKE_SIMILARITY {
+                // Ranked by what the pair would add to the score, so the reading with fewer faults wins a close call
+                let contribution = if reversed { similarity * (1f32 - FAULT_PENALTY) } else { similarity };
+                let order_difference = (i as f32 / input_strokes.len() as f32 - t as f32 / templates.len() as f32).abs();
+                candidates.push(Pairing {
+                    input: i,
+                    template: t,
+                    similarity,
+                    reversed,
+                    rank: contribution - ORDER_PRIOR_WEIGHT * order_difference,
+                });
+            }
+        }
+    }
+
+    // Pair greedily, best first
+    candidates.sort_by(|a, b| b.rank.partial_cmp(&a.rank).unwrap_or(std::cmp::Ordering::Equal));
+    let mut pairings: Vec<Option<Pairing>> = vec![None; input_strokes.len()];
+    let mut template_paired = vec![false; templates.len()];
+    for candidate in candidates {
+        if pairings[candidate.input].is_none() && !template_paired[candidate.template] {
+            pairings[candidate.input] = Some(candidate);
+            template_paired[candidate.template] = true;
+        }
+    }
+
+    // Paired strokes in the longest run that follows the template's order are in order; the rest are not
+    let paired: Vec<Pairing> = pairings.iter().filter_map(|pairing| *pairing).collect();
+    let in_order = get_longest_increasing(&paired.iter().map(|pairing| pairing.template).collect::<Vec<usize>>());
+
+    let mut strokes: Vec<StrokeGrade> = Vec::with_capacity(input_strokes.len() + templates.len());
+    let mut total = 0f32;
+    let mut paired_ix = 0;
+    for (i, pairing) in pairings.iter().enumerate() {
+        let pairing = match pairing {
+            Some(pairing) => pairing,
+            None => {
+                strokes.push(StrokeGrade {
+                    input: Some(input_strokes[i].index),
+                    template: None,
+                    similarity: 0f32,
+                    faults: vec![StrokeFault::Extra],
+                });
+                continue;
+            }
+        };
+        let mut faults: Vec<StrokeFault> = Vec::new();
+        if !in_order[paired_ix] {
+            faults.push(StrokeFault::WrongOrder);
+        }
+        paired_ix += 1;
+        if pairing.reversed {
+            faults.push(StrokeFault::Reversed);
+        }
+        let input_center = get_input_center(&input_strokes[i].sub_strokes);
+        let template_center = get_template_center(templates[pairing.template]);
+        if matcher.position_score(input_center.0 - template_center.0, input_center.1 - template_center.1) < MIN_POSITION_SCORE {
+            faults.push(StrokeFault::WrongPosition);
+        }
+        let similarity = pairing.similarity.clamp(0f32, 1f32);
+        total += similarity * f32::max(0f32, 1f32 - FAULT_PENALTY * faults.len() as f32);
+        strokes.push(StrokeGrade {
+            input: Some(input_strokes[i].index),
+            template: Some(pairing.template),
+            similarity,
+            faults,
+        });
+    }
+    for (t, &paired) in template_paired.iter().enumerate() {
+        if !paired {
+            strokes.push(StrokeGrade {
+                input: None,
+                template: Some(t),
+                similarity: 0f32,
+                faults: vec![StrokeFault::Missing],
+            });
+        }
+    }
+
+    let stroke_count = usize::max(input_strokes.len(), templates.len());
+    Grade {
+        hanzi: char_data.hanzi,
+        tag: char_data.tag.clone(),
+        region: char_data.region.clone(),
+        score: if stroke_count == 0 { 0f32 } else { total / stroke_count as f32 },
+        strokes,
+    }
+}
+
+// Similarity of an input stroke to a template stroke, and whether it fits better written the other way round.
+// backwards is the input stroke's substrokes as reverse_sub_strokes returns them.
+pub(crate) fn compare_both_ways(matcher: &Matcher, forwards: &[SubStroke], backwards: &[SubStroke], template: &[SubStrokeTriple]) -> (f32, bool) {
+    let forward = matcher.compare_stroke(forwards, template);
+    let backward = matcher.compare_stroke(backwards, template);
+    let reversed = backward > forward + REVERSED_MARGIN;
+    (if reversed { backward } else { forward }, reversed)
+}
+
+// The same substrokes, written from the end
+pub(crate) fn reverse_sub_strokes(sub_strokes: &[SubStroke]) -> Vec<SubStroke> {
+    sub_strokes.iter().rev().map(|sub_stroke| SubStroke {
+        direction: (sub_stroke.direction + 128f32) % 256f32,
+        ..*sub_stroke
+    }).collect()
+}
+
+// Length-weighted mean of the substroke centers
+pub(crate) fn get_input_center(sub_strokes: &[SubStroke]) -> (f32, f32) {
+    let mut sum = (0f32, 0f32, 0f32);
+    for sub_stroke in sub_strokes {
+        let weight = sub_stroke.length + 1f32;
+        sum = (sum.0 + sub_stroke.center_x * weight, sum.1 + sub_stroke.center_y * weight, sum.2 + weight);
+    }
+    if sum.2 == 0f32 { (0f32, 0f32) } else { (sum.0 / sum.2, sum.1 / sum.2) }
+}
+
+pub(crate) fn get_template_center(sub_strokes: &[SubStrokeTriple]) -> (f32, f32) {
+    let mut sum = (0f32, 0f32, 0f32);
+    for sub_stroke in sub_strokes {
+        let weight = sub_stroke.length as f32 + 1f32;
+        sum = (sum.0 + (sub_stroke.center >> 4) as f32 * weight, sum.1 + (sub_stroke.center & 0x0f) as f32 * weight, sum.2 + weight);
+    }
+    if sum.2 == 0f32 { (0f32, 0f32) } else { (sum.0 / sum.2, sum.1 / sum.2) }
+}
+
+// Marks the items that make up a longest strictly increasing subsequence
+fn get_longest_increasing(values: &[usize]) -> Vec<bool> {
+    // lengths[i]: longest such run ending at i; prevs[i]: the item before i in it
+    let mut lengths: Vec<usize> = vec![1; values.len()];
+    let mut prevs: Vec<Option<usize>> = vec![None; values.len()];
+    for i in 0..values.len() {
+        for j in 0..i {
+            if values[j] < values[i] && lengths[j] + 1 > lengths[i] {
+                lengths[i] = lengths[j] + 1;
+                prevs[i] = Some(j);
+            }
+        }
+    }
+    let mut res = vec![false; values.len()];
+    let mut ix = (0..values.len()).max_by_key(|&i| (lengths[i], std::cmp::Reverse(i)));
+    while let Some(i) = ix {
+        res[i] = true;
+        ix = prevs[i];
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_samples::*;
+    use super::super::recognizer::*;
+
+    fn faults(grade: &Grade) -> Vec<(Option<usize>, Vec<StrokeFault>)> {
+        grade.strokes.iter().map(|stroke| (stroke.input, stroke.faults.clone())).collect()
+    }
+
+    #[test]
+    fn test_longest_increasing() {
+        assert_eq!(get_longest_increasing(&vec![0, 1, 2]), [true, true, true]);
+        assert_eq!(get_longest_increasing(&vec![1, 0]), [true, false]);
+        assert_eq!(get_longest_increasing(&vec![0, 3, 1, 2]), [true, false, true, true]);
+        assert_eq!(get_longest_increasing(&vec![]).len(), 0);
+    }
+
+    #[test]
+    fn test_grade() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let options = LookupOptions::default();
+        let strokes = parse_sample(STROKES_2);
+        let grade = recognizer.grade(&strokes, '十', &options).unwrap();
+        assert!(grade.strokes.iter().all(|stroke| stroke.is_correct()));
+        assert_eq!(grade.strokes[0].template, Some(0));
+        assert_eq!(grade.strokes[1].template, Some(1));
+        assert!(grade.score > 0.7);
+        assert!(recognizer.grade(&strokes, 'x', &options).is_none());
+
+        // Vertical first
+        let swapped = vec![Stroke { points: strokes[1].points.clone() }, Stroke { points: strokes[0].points.clone() }];
+        let swapped_grade = recognizer.grade(&swapped, '十', &options).unwrap();
+        assert_eq!(faults(&swapped_grade), [(Some(0), vec![]), (Some(1), vec![StrokeFault::WrongOrder])]);
+        assert!(swapped_grade.score < grade.score);
+
+        // Vertical drawn bottom up
+        let mut reversed = parse_sample(STROKES_2);
+        reversed[1].points.reverse();
+        let reversed_grade = recognizer.grade(&reversed, '十', &options).unwrap();
+        assert_eq!(faults(&reversed_grade), [(Some(0), vec![]), (Some(1), vec![StrokeFault::Reversed])]);
+
+        // Horizontal at the very top
+        let mut moved = parse_sample(STROKES_2);
+        for point in moved[0].points.iter_mut() {
+            point.y -= 62;
+        }
+        let moved_grade = recognizer.grade(&moved, '十', &options).unwrap();
+        assert_eq!(faults(&moved_grade), [(Some(0), vec![StrokeFault::WrongPosition]), (Some(1), vec![])]);
+
+        // Only the horizontal, then with a stray stroke added
+        let partial = vec![Stroke { points: strokes[0].points.clone() }];
+        let partial_grade = recognizer.grade(&partial, '十', &options).unwrap();
+        assert_eq!(faults(&partial_grade), [(Some(0), vec![]), (None, vec![StrokeFault::Missing])]);
+        let mut extra = parse_sample(STROKES_2);
+        extra.push(Stroke { points: vec![Point { x: 160, y: 190 }, Point { x: 100, y: 70 }] });
+        let extra_grade = recognizer.grade(&extra, '十', &options).unwrap();
+        assert_eq!(faults(&extra_grade), [(Some(0), vec![]), (Some(1), vec![]), (Some(2), vec![StrokeFault::Extra])]);
+
+        // Every stroke of a well-written 氣 pairs with its own template stroke
+        let grade = recognizer.grade(&parse_sample(STROKES_4), '氣', &options).unwrap();
+        for (i, stroke) in grade.strokes.iter().enumerate() {
+            assert_eq!((stroke.input, stroke.template), (Some(i), Some(i)));
+            assert!(stroke.is_correct());
+        }
+    }
+}
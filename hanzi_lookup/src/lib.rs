@@ -2,17 +2,34 @@
 #![allow(unused_imports)]
 
 extern crate wasm_bindgen;
+extern crate serde_wasm_bindgen;
 extern crate serde_derive;
 extern crate bincode;
 
 mod analyzed_character;
+mod components;
 mod cubic_curve_2d;
+mod dataset;
 mod entities;
+mod error;
+mod explanation;
+mod grading;
 mod match_collector;
 mod matcher;
+mod ngram;
+mod preprocessing;
+mod prior;
+mod recognizer;
+mod segmentation;
+mod segmenter;
+mod session;
+#[cfg(test)]
+mod test_samples;
+mod tracing;
+mod write_over;
 
 use serde_derive::{Deserialize, Serialize};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use wasm_bindgen::prelude::*;
 
 use match_collector::*;
@@ -20,6 +37,21 @@ use analyzed_character::*;
 use match_collector::*;
 use matcher::*;
 
+pub use dataset::{CharData, Dataset, DatasetError, SubStrokeTriple};
+pub use error::Error;
+pub use explanation::{AlignmentStep, Explanation, SubStrokeScores};
+pub use grading::{Grade, StrokeFault, StrokeGrade};
+pub use matcher::{AutoWiden, CharFilter, LookupOptions, MatcherParams, RegionFilter};
+pub use ngram::NgramModel;
+pub use preprocessing::{Deduplicate, DropTinyStrokes, JoinStrokes, Pipeline, Preprocessor, RemoveHooks, Resample, Smooth};
+pub use prior::FrequencyPrior;
+pub use recognizer::Recognizer;
+pub use segmentation::{LineDirection, LineMatch, LineSegment};
+pub use segmenter::{CornerSegmenter, RdpSegmenter, Segmenter};
+pub use session::Session;
+pub use tracing::{StrokeHint, SubStrokeHint, TraceStep, Tracer};
+pub use write_over::{WriteOver, WriteOverOptions};
+
 #[derive(Serialize, Deserialize)]
 struct Action {
     action: String,
@@ -34,53 +66,525 @@ struct Input {
     actions: Vec<Action>,
 }
 
+#[derive(Serialize)]
+struct ConfidentResult {
+    // Empty if nothing matched with the minimum confidence
+    matches: Vec<Match>,
+    ambiguous: bool,
+}
+
+// One stage of a preprocessing pipeline as JS configures it, e.g. {"stage": "resample", "spacing": 4}
+#[derive(Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+enum StageConfig {
+    Deduplicate,
+    Resample(Resample),
+    Smooth(Smooth),
+    RemoveHooks(RemoveHooks),
+    DropTinyStrokes(DropTinyStrokes),
+    JoinStrokes(JoinStrokes),
+}
+
+// How strokes are split into substrokes as JS configures it, e.g. {"segmenter": "rdp", "tolerance": 6}
+#[derive(Deserialize)]
+#[serde(tag = "segmenter", rename_all = "snake_case")]
+enum SegmenterConfig {
+    Corner(CornerSegmenter),
+    Rdp(RdpSegmenter),
+}
+
+#[derive(Serialize)]
+struct WriteOverResult {
+    // Top candidates of the characters that are done, in writing order
+    committed: Vec<Match>,
+    // Best matches for the character still being written
+    candidates: Vec<Match>,
+}
+
+#[derive(Serialize)]
+struct TraceResult {
+    // None if the last stroke was ignored as a slip of the pen
+    step: Option<TraceStep>,
+    next: Option<StrokeHint>,
+}
+
+
+// Throws instead of trapping on malformed input, so the wasm instance stays usable
+#[wasm_bindgen]
+pub fn lookup(input: &JsValue, limit: usize) -> Result<String, JsValue> {
+    lookup_on_canvas(input, limit, LookupOptions::default().canvas_size)
+}
+
+// Same as lookup, for coordinates on a canvas of size x size instead of 256 x 256
+#[wasm_bindgen]
+pub fn lookup_on_canvas(input: &JsValue, limit: usize, canvas_size: f32) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = LookupOptions { canvas_size, ..js_lookup_options() };
+    let lookup_res = try_match_typed_with(&strokes, limit, &options).map_err(to_js_error)?;
+    serde_json::to_string(&lookup_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Same as lookup, but only considers the characters in chars; or all but those if exclude is true
+#[wasm_bindgen]
+pub fn lookup_filtered(input: &JsValue, limit: usize, chars: &str, exclude: bool) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = LookupOptions { filter: Some(if exclude { CharFilter::deny_str(chars) } else { CharFilter::allow_str(chars) }), ..js_lookup_options() };
+    let lookup_res = try_match_typed_with(&strokes, limit, &options).map_err(to_js_error)?;
+    serde_json::to_string(&lookup_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Same as lookup, with candidates reranked by how likely they are to follow the text written before
+#[wasm_bindgen]
+pub fn lookup_in_context(input: &JsValue, limit: usize, context: &str) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = LookupOptions { context: Some(context.to_string()), ..js_lookup_options() };
+    let lookup_res = try_match_typed_with(&strokes, limit, &options).map_err(to_js_error)?;
+    serde_json::to_string(&lookup_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Same as lookup, but the strokes may be just the beginning of a character with more strokes
+#[wasm_bindgen]
+pub fn lookup_prefix(input: &JsValue, limit: usize) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = LookupOptions { prefix: true, ..js_lookup_options() };
+    let lookup_res = try_match_typed_with(&strokes, limit, &options).map_err(to_js_error)?;
+    serde_json::to_string(&lookup_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Same as lookup, but order_free_weight of the score ignores the order in which the strokes were written
+#[wasm_bindgen]
+pub fn lookup_any_order(input: &JsValue, limit: usize, order_free_weight: f32) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = LookupOptions { order_free_weight, ..js_lookup_options() };
+    let lookup_res = try_match_typed_with(&strokes, limit, &options).map_err(to_js_error)?;
+    serde_json::to_string(&lookup_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Same as lookup, but leaves out matches below min_confidence and tells if the best two are nearly tied
+#[wasm_bindgen]
+pub fn lookup_confident(input: &JsValue, limit: usize, min_confidence: f32) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = LookupOptions { min_confidence, ..js_lookup_options() };
+    let matches = try_match_typed_with(&strokes, limit, &options).map_err(to_js_error)?;
+    let confident_res = ConfidentResult {
+        ambiguous: is_ambiguous(&matches),
+        matches,
+    };
+    serde_json::to_string(&confident_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Like lookup, but each match comes with the alignment of input and template substrokes behind its score
+#[wasm_bindgen]
+pub fn explain(input: &JsValue, limit: usize) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = js_lookup_options();
+    let explanations = with_recognizer(|recognizer| {
+        if limit == 0 {
+            return Err(Error::ZeroLimit);
+        }
+        recognizer::check_strokes(&strokes)?;
+        Ok(recognizer.explain(&strokes, limit, &options))
+    }).map_err(to_js_error)?;
+    serde_json::to_string(&explanations).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Characters that contain the drawn strokes as a component, such as 氵 or 钅
+#[wasm_bindgen]
+pub fn lookup_component(input: &JsValue, limit: usize) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = js_lookup_options();
+    let lookup_res = with_recognizer(|recognizer| {
+        if limit == 0 {
+            return Err(Error::ZeroLimit);
+        }
+        recognizer::check_strokes(&strokes)?;
+        Ok(recognizer.lookup_component(&strokes, limit, &options))
+    }).map_err(to_js_error)?;
+    serde_json::to_string(&lookup_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Reads a line of characters written side by side, or top to bottom if vertical, on a canvas of any size.
+// Returns the best split into characters, with up to limit candidates at each position.
+#[wasm_bindgen]
+pub fn lookup_line(input: &JsValue, limit: usize, vertical: bool) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let direction = if vertical { LineDirection::Vertical } else { LineDirection::Horizontal };
+    let options = js_lookup_options();
+    let line_res = with_recognizer(|recognizer| recognizer.try_lookup_line(&strokes, direction, limit, &options)).map_err(to_js_error)?;
+    serde_json::to_string(&line_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Grades the strokes as an attempt at writing target: per-stroke faults and an overall score
+#[wasm_bindgen]
+pub fn grade(input: &JsValue, target: char) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let options = js_lookup_options();
+    let grade = with_recognizer(|recognizer| {
+        recognizer::check_strokes(&strokes)?;
+        recognizer.grade(&strokes, target, &options).ok_or(Error::UnknownCharacter(target))
+    }).map_err(to_js_error)?;
+    serde_json::to_string(&grade).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Characters written on top of each other in the same box: input is all strokes so far, with timestamps if available.
+// Returns the characters that are done, and the candidates for the one still being written.
+#[wasm_bindgen]
+pub fn lookup_write_over(input: &JsValue, limit: usize) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let write_over_res = with_recognizer(|recognizer| {
+        if limit == 0 {
+            return Err(Error::ZeroLimit);
+        }
+        recognizer::check_strokes(&strokes)?;
+        let options = WriteOverOptions { lookup: js_lookup_options(), limit, ..WriteOverOptions::default() };
+        let mut write_over = WriteOver::with_options(recognizer, options);
+        for stroke in strokes {
+            write_over.add_stroke(stroke);
+        }
+        Ok(WriteOverResult {
+            committed: write_over.committed().to_vec(),
+            candidates: write_over.candidates().to_vec(),
+        })
+    }).map_err(to_js_error)?;
+    serde_json::to_string(&write_over_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Guided writing: input is the strokes accepted so far followed by the one just drawn.
+// Reports whether that last stroke was accepted, and where the next stroke goes.
+#[wasm_bindgen]
+pub fn trace(input: &JsValue, target: char) -> Result<String, JsValue> {
+    let strokes = parse_js_strokes(input).map_err(to_js_error)?;
+    let trace_res = with_recognizer(|recognizer| {
+        recognizer::check_strokes(&strokes)?;
+        let mut tracer = recognizer.tracer(target).ok_or(Error::UnknownCharacter(target))?;
+        let mut step = None;
+        for stroke in strokes {
+            step = tracer.add_stroke(stroke);
+        }
+        Ok(TraceResult {
+            step,
+            next: tracer.next_hint(),
+        })
+    }).map_err(to_js_error)?;
+    serde_json::to_string(&trace_res).map_err(|err| to_js_error(Error::Serialization(err.to_string())))
+}
+
+// Replaces the dataset used by lookup; the only way to get matches if the embedded data is compiled out
+#[wasm_bindgen]
+pub fn load_dataset(data: &[u8]) -> Result<(), JsValue> {
+    let recognizer = Recognizer::from_bytes(data).map_err(|err| to_js_error(Error::from(err)))?;
+    RECOGNIZER.with(|cell| *cell.borrow_mut() = Some(recognizer));
+    Ok(())
+}
+
+// Sets the character frequency table lookups use to favor common characters; one character, a tab and its count per line.
+// weight is the share of the score that depends on how common the character is, from 0.0 to 1.0; 0.1 is a good start.
+#[wasm_bindgen]
+pub fn load_frequencies(table: &str, weight: f32) -> Result<(), JsValue> {
+    if !(0.0..=1.0).contains(&weight) {
+        return Err(to_js_error(Error::InvalidOptions(format!("Prior weight must be between 0 and 1, got {}.", weight))));
+    }
+    let prior = FrequencyPrior::parse(table).map_err(to_js_error)?;
+    with_recognizer_mut(|recognizer| recognizer.set_prior(Some(prior))).map_err(to_js_error)?;
+    PRIOR_WEIGHT.with(|prior_weight| prior_weight.set(Some(weight)));
+    Ok(())
+}
+
+// Sets the bigram and trigram counts lookup_in_context reranks by; two or three characters, a tab and the count per line
+#[wasm_bindgen]
+pub fn load_ngrams(table: &str) -> Result<(), JsValue> {
+    let language_model = NgramModel::parse(table).map_err(to_js_error)?;
+    with_recognizer_mut(|recognizer| recognizer.set_language_model(Some(language_model))).map_err(to_js_error)
+}
 
+// Sets the cleanup run on strokes before lookup: an array of stages, such as [{"stage": "deduplicate"}, {"stage": "smooth", "passes": 2}].
+// Settings left out take their defaults; an empty array turns preprocessing off.
 #[wasm_bindgen]
-pub fn lookup(input: &JsValue, limit: usize) -> String {
+pub fn set_preprocessing(stages: &JsValue) -> Result<(), JsValue> {
+    let stages: Vec<StageConfig> = serde_wasm_bindgen::from_value(stages.clone()).map_err(|err| to_js_error(Error::InvalidInput(err.to_string())))?;
+    let mut pipeline = Pipeline::new();
+    for stage in stages {
+        match stage {
+            StageConfig::Deduplicate => pipeline.push(Deduplicate),
+            StageConfig::Resample(stage) => pipeline.push(stage),
+            StageConfig::Smooth(stage) => pipeline.push(stage),
+            StageConfig::RemoveHooks(stage) => pipeline.push(stage),
+            StageConfig::DropTinyStrokes(stage) => pipeline.push(stage),
+            StageConfig::JoinStrokes(stage) => pipeline.push(stage),
+        }
+    }
+    let preprocessing = if pipeline.is_empty() { None } else { Some(pipeline) };
+    with_recognizer_mut(|recognizer| recognizer.set_preprocessing(preprocessing)).map_err(to_js_error)
+}
+
+// Replaces the recognizer's segmenter; {"segmenter": "corner"} restores the default
+#[wasm_bindgen]
+pub fn set_segmenter(config: &JsValue) -> Result<(), JsValue> {
+    let config: SegmenterConfig = serde_wasm_bindgen::from_value(config.clone()).map_err(|err| to_js_error(Error::InvalidInput(err.to_string())))?;
+    with_recognizer_mut(|recognizer| match config {
+        SegmenterConfig::Corner(segmenter) => recognizer.set_segmenter(segmenter),
+        SegmenterConfig::Rdp(segmenter) => recognizer.set_segmenter(segmenter),
+    }).map_err(to_js_error)
+}
+
+fn to_js_error(err: Error) -> JsValue {
+    JsValue::from_str(&err.to_string())
+}
+
+fn parse_js_strokes(input: &JsValue) -> Result<Vec<Stroke<PenPoint>>, Error> {
     // Input is vector of vector of vector of numbers - how strokes and their points are represented in JS
-    let input: Vec<Vec<Vec<f32>>> = input.into_serde().unwrap();
-    // Convert to typed form: vector of strokes
-    let mut strokes: Vec<Stroke> = Vec::with_capacity(input.len());
+    // Parsed as f64 so that timestamps like Date.now() keep their precision
+    let input: Vec<Vec<Vec<f64>>> = serde_wasm_bindgen::from_value(input.clone()).map_err(|err| Error::InvalidInput(err.to_string()))?;
+    convert_strokes(&input)
+}
+
+// Converts untyped input into strokes; every point is [x, y], [x, y, time] or [x, y, time, pressure]
+fn convert_strokes(input: &[Vec<Vec<f64>>]) -> Result<Vec<Stroke<PenPoint>>, Error> {
+    let mut strokes: Vec<Stroke<PenPoint>> = Vec::with_capacity(input.len());
     for i in 0..input.len() {
         let mut stroke = Stroke {
             points: Vec::with_capacity(input[i].len()),
         };
         for j in 0..input[i].len() {
-            stroke.points.push(Point {
-                x: input[i][j][0].round() as u8,
-                y: input[i][j][1].round() as u8,
+            let values = &input[i][j];
+            if values.len() < 2 || values.len() > 4 {
+                return Err(Error::InvalidInput(format!("Point {} of stroke {} is not an X, Y pair with optional time and pressure.", j, i)));
+            }
+            stroke.points.push(PenPoint {
+                x: values[0] as f32,
+                y: values[1] as f32,
+                time: values.get(2).cloned(),
+                pressure: values.get(3).map(|pressure| *pressure as f32),
             });
         }
         strokes.push(stroke);
     }
-    let lookup_res = match_typed(&strokes, limit);
-    serde_json::to_string(&lookup_res).unwrap()
+    Ok(strokes)
+}
+
+// Anything the analysis can read X and Y coordinates from
+// Pen input may also know when each point was sampled and how hard the pen pressed; analysis uses these when present
+pub trait InputPoint: Copy {
+    fn x(&self) -> f32;
+    fn y(&self) -> f32;
+    // Milliseconds, from any origin
+    fn time(&self) -> Option<f64> { None }
+    // 0 to 1, as reported by pointer events
+    fn pressure(&self) -> Option<f32> { None }
 }
 
+// Point on the classic 256 x 256 canvas
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Point {
     pub x: u8,
     pub y: u8,
 }
 
+impl InputPoint for Point {
+    fn x(&self) -> f32 { self.x as f32 }
+    fn y(&self) -> f32 { self.y as f32 }
+}
+
+// Point on a canvas of any size; set LookupOptions::canvas_size to match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointF {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl InputPoint for PointF {
+    fn x(&self) -> f32 { self.x }
+    fn y(&self) -> f32 { self.y }
+}
+
+// Point from a pen or tablet, with timestamp and pressure where the device reports them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PenPoint {
+    pub x: f32,
+    pub y: f32,
+    pub time: Option<f64>,
+    pub pressure: Option<f32>,
+}
+
+impl InputPoint for PenPoint {
+    fn x(&self) -> f32 { self.x }
+    fn y(&self) -> f32 { self.y }
+    fn time(&self) -> Option<f64> { self.time }
+    fn pressure(&self) -> Option<f32> { self.pressure }
+}
+
 #[derive(Debug)]
-pub struct Stroke {
-    pub points: Vec<Point>,
+pub struct Stroke<P = Point> {
+    pub points: Vec<P>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Match {
     pub hanzi: char,
     pub score: f32,
+    // Tag of the character's template that matched best; None for its standard template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    // Region of that template; None if it is the form used everywhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    // How close the drawing came to a perfect rendering of the template, from 0.0 to 1.0; unlike the score, comparable across inputs
+    #[serde(default)]
+    pub confidence: f32,
+}
+
+impl Match {
+    // A match against a character's standard template, with no confidence worked out
+    pub fn new(hanzi: char, score: f32) -> Match {
+        Match {
+            hanzi,
+            score,
+            tag: None,
+            region: None,
+            confidence: 0.0,
+        }
+    }
+}
+
+// Top two matches closer than this, relative to the best score, are a toss-up
+const AMBIGUOUS_MARGIN: f32 = 0.03;
+
+// True if the best two matches are nearly tied, so the writer should pick rather than get the first one.
+// The matches must be sorted by score, as lookup returns them.
+pub fn is_ambiguous(matches: &[Match]) -> bool {
+    match (matches.first(), matches.get(1)) {
+        (Some(best), Some(second)) => best.score - second.score < AMBIGUOUS_MARGIN * best.score.abs(),
+        _ => false,
+    }
+}
+
+thread_local!(static RECOGNIZER: RefCell<Option<Recognizer>> = const { RefCell::new(None) });
+
+// Share of the score that the table given to load_frequencies takes up in the wasm lookups; None until one is loaded
+thread_local!(static PRIOR_WEIGHT: Cell<Option<f32>> = const { Cell::new(None) });
+
+// Options for the wasm lookups: the defaults, with the prior weight load_frequencies was given
+fn js_lookup_options() -> LookupOptions {
+    LookupOptions { prior_weight: PRIOR_WEIGHT.with(|weight| weight.get()), ..LookupOptions::default() }
+}
+
+#[cfg(feature = "embedded-data")]
+fn default_recognizer() -> Result<Recognizer, Error> {
+    Ok(Recognizer::new())
+}
+
+#[cfg(not(feature = "embedded-data"))]
+fn default_recognizer() -> Result<Recognizer, Error> {
+    Err(Error::NoDataset)
+}
+
+// Convenience lookup through a per-thread default recognizer.
+// To share one dataset between threads, or to use custom parameters, create a Recognizer instead.
+// Panics if the library is built without the embedded-data feature and load_dataset hasn't been called on this thread;
+// try_match_typed returns Error::NoDataset instead.
+pub fn match_typed<P: InputPoint>(strokes: &[Stroke<P>], limit: usize) -> Vec<Match> {
+    with_recognizer(|recognizer| Ok(recognizer.lookup(strokes, limit)))
+        .unwrap_or_else(|err| panic!("{} Call load_dataset first, or use try_match_typed.", err))
+}
+
+// Like match_typed, but returns an error for malformed input, a zero limit, or a missing dataset
+pub fn try_match_typed<P: InputPoint>(strokes: &[Stroke<P>], limit: usize) -> Result<Vec<Match>, Error> {
+    try_match_typed_with(strokes, limit, &LookupOptions::default())
+}
+
+pub fn try_match_typed_with<P: InputPoint>(strokes: &[Stroke<P>], limit: usize, options: &LookupOptions) -> Result<Vec<Match>, Error> {
+    with_recognizer(|recognizer| recognizer.try_lookup_with(strokes, limit, options))
+}
+
+// Runs f with the per-thread recognizer, creating it on first use
+fn with_recognizer<T, F>(f: F) -> Result<T, Error>
+    where F: FnOnce(&Recognizer) -> Result<T, Error> {
+    RECOGNIZER.with(|cell| {
+        let mut recognizer = cell.borrow_mut();
+        if recognizer.is_none() {
+            *recognizer = Some(default_recognizer()?);
+        }
+        f(recognizer.as_ref().unwrap())
+    })
+}
+
+fn with_recognizer_mut<F>(f: F) -> Result<(), Error>
+    where F: FnOnce(&mut Recognizer) {
+    RECOGNIZER.with(|cell| {
+        let mut recognizer = cell.borrow_mut();
+        if recognizer.is_none() {
+            *recognizer = Some(default_recognizer()?);
+        }
+        f(recognizer.as_mut().unwrap());
+        Ok(())
+    })
 }
 
-thread_local!(static MATCHER: RefCell<Matcher> = RefCell::new(Matcher::new()));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_strokes() {
+        let strokes = convert_strokes(&vec![vec![vec![10.4, 20.6], vec![300.0, 0.0]]]).unwrap();
+        assert_eq!(strokes[0].points, [
+            PenPoint { x: 10.4, y: 20.6, time: None, pressure: None },
+            PenPoint { x: 300.0, y: 0.0, time: None, pressure: None },
+        ]);
+        let strokes = convert_strokes(&vec![vec![vec![10.0, 20.0, 1571234567890.0], vec![30.0, 40.0, 1571234567906.0, 0.5]]]).unwrap();
+        assert_eq!(strokes[0].points[1].time.unwrap() - strokes[0].points[0].time.unwrap(), 16.0);
+        assert_eq!(strokes[0].points[0].pressure, None);
+        assert_eq!(strokes[0].points[1].pressure, Some(0.5));
+        assert!(match convert_strokes(&vec![vec![vec![10.0]]]) { Err(Error::InvalidInput(_)) => true, _ => false });
+        assert!(match convert_strokes(&vec![vec![vec![1.0, 2.0, 3.0, 4.0, 5.0]]]) { Err(Error::InvalidInput(_)) => true, _ => false });
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-data")]
+    fn test_try_match_typed() {
+        let dot = convert_strokes(&vec![vec![vec![128.0, 128.0]]]).unwrap();
+        assert!(match try_match_typed(&dot, 8) { Err(Error::TooFewPoints { stroke: 0 }) => true, _ => false });
+        let stroke = convert_strokes(&vec![vec![vec![70.0, 124.0], vec![191.0, 124.0]]]).unwrap();
+        assert!(match try_match_typed(&stroke, 0) { Err(Error::ZeroLimit) => true, _ => false });
+        assert!(try_match_typed(&stroke, 8).unwrap()[0].hanzi == '一');
+        let nan = convert_strokes(&vec![vec![vec![70.0, 124.0], vec![std::f64::NAN, 124.0]]]).unwrap();
+        assert!(match try_match_typed(&nan, 8) { Err(Error::InvalidCoordinate { stroke: 0, point: 1 }) => true, _ => false });
+        let nan_time = convert_strokes(&vec![vec![vec![70.0, 124.0, 0.0], vec![191.0, 124.0, std::f64::NAN]]]).unwrap();
+        assert!(match try_match_typed(&nan_time, 8) { Err(Error::InvalidPenData { stroke: 0, point: 1 }) => true, _ => false });
+    }
+
+    #[test]
+    #[cfg(not(feature = "embedded-data"))]
+    fn test_no_dataset() {
+        let stroke = convert_strokes(&vec![vec![vec![70.0, 124.0], vec![191.0, 124.0]]]).unwrap();
+        assert!(match try_match_typed(&stroke, 8) { Err(Error::NoDataset) => true, _ => false });
+    }
+
+    #[test]
+    #[cfg(not(feature = "embedded-data"))]
+    #[should_panic(expected = "try_match_typed")]
+    fn test_match_typed_without_dataset() {
+        let stroke = convert_strokes(&vec![vec![vec![70.0, 124.0], vec![191.0, 124.0]]]).unwrap();
+        match_typed(&stroke, 8);
+    }
+
+    #[test]
+    fn test_match_json() {
+        let m = Match { tag: Some("alt-order".to_string()), confidence: 0.5, ..Match::new('火', 1.5) };
+        let json = serde_json::to_string(&m).unwrap();
+        assert_eq!(json, r#"{"hanzi":"火","score":1.5,"tag":"alt-order","confidence":0.5}"#);
+        let read: Match = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, m);
+        let read: Match = serde_json::from_str(r#"{"hanzi":"火","score":1.5}"#).unwrap();
+        assert_eq!((read.tag, read.region, read.confidence), (None, None, 0.0));
+    }
 
-pub fn match_typed(strokes: &Vec<Stroke>, limit: usize) -> Vec<Match> {
-    let mut res: Vec<Match> = Vec::with_capacity(limit);
-    let mut collector = MatchCollector::new(&mut res, limit);
-    MATCHER.with(|matcher| {
-        matcher.borrow_mut().lookup(strokes, &mut collector);
-    });
-    res
+    #[test]
+    fn test_is_ambiguous() {
+        assert!(!is_ambiguous(&[]));
+        assert!(!is_ambiguous(&[Match::new('己', 10.0)]));
+        assert!(is_ambiguous(&[Match::new('己', 10.0), Match::new('已', 9.8)]));
+        assert!(!is_ambiguous(&[Match::new('己', 10.0), Match::new('已', 9.0)]));
+        assert!(is_ambiguous(&[Match::new('己', -1.0), Match::new('已', -1.01)]));
+    }
 }
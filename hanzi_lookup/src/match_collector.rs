@@ -79,10 +79,7 @@ mod tests {
     #[should_panic]
     fn test_new_fail2() {
         let mut matches: Vec<Match> = Vec::new();
-        matches.push(Match {
-            hanzi: '我',
-            score: 1.0,
-        });
+        matches.push(Match::new('我', 1.0));
         let mut _collector = MatchCollector::new(&mut matches, 1);
     }
 
@@ -90,32 +87,32 @@ mod tests {
     fn test_filing() {
         let mut matches: Vec<Match> = Vec::new();
         let mut collector = MatchCollector::new(&mut matches, 3);
-        let mc1 = Match {
-            hanzi: '我',
-            score: 0.8,
-        };
-        let mc2 = Match {
-            hanzi: '你',
-            score: 0.9,
-        };
-        let mc3 = Match {
-            hanzi: '我',
-            score: 0.7,
-        };
-        let mc4 = Match {
-            hanzi: '他',
-            score: 0.7,
-        };
-        let mc5 = Match {
-            hanzi: '鸡',
-            score: 1.0,
-        };
-        collector.file_match(mc1);
-        collector.file_match(mc2);
+        let mc1 = Match::new('我', 0.8);
+        let mc2 = Match::new('你', 0.9);
+        let mc3 = Match::new('我', 0.7);
+        let mc4 = Match::new('他', 0.7);
+        let mc5 = Match::new('鸡', 1.0);
+        collector.file_match(mc1.clone());
+        collector.file_match(mc2.clone());
         collector.file_match(mc3);
         collector.file_match(mc4); 
-        collector.file_match(mc5);
+        collector.file_match(mc5.clone());
         assert_eq!(matches, [mc5, mc2, mc1]);
     }
-}
 
+    #[test]
+    fn test_filing_templates() {
+        let mut matches: Vec<Match> = Vec::new();
+        let mut collector = MatchCollector::new(&mut matches, 3);
+        let mc1 = Match::new('我', 0.8);
+        let mc2 = Match::new('你', 0.9);
+        collector.file_match(mc1);
+        collector.file_match(mc2.clone());
+        // Another template of a character already there replaces it if it scores higher, and is dropped if not
+        let mc3 = Match { tag: Some("alt-order".to_string()), ..Match::new('我', 0.95) };
+        let mc4 = Match { tag: Some("alt-order".to_string()), ..Match::new('你', 0.85) };
+        collector.file_match(mc3.clone());
+        collector.file_match(mc4);
+        assert_eq!(matches, [mc3, mc2]);
+    }
+}
@@ -1,35 +1,17 @@
+use std::collections::{HashMap, HashSet};
+
+use super::dataset::*;
 use super::entities::*;
 use super::cubic_curve_2d::*;
 use super::match_collector::*;
 use super::analyzed_character::*;
+use super::explanation::*;
+use super::prior::*;
 use super::*;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct SubStrokeTriple {
-    dir: u8,
-    length: u8,
-    center: u8,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct CharData {
-    hanzi: char,
-    stroke_count: u16,
-    sub_strokes: Vec<SubStrokeTriple>,
-}
-
-thread_local!(static CHAR_DATA: Vec<CharData> = load_strokes());
-
-fn load_strokes() -> Vec<CharData> {
-    let hwbytes = include_bytes!("../data/mmah.bin");
-    let reader = std::io::BufReader::new(&hwbytes[..]);
-    let res = bincode::deserialize_from(reader).expect("Failed to deserialize.");
-    res
-}
-
 // The algorithm's magic numbers. Allow shouting snake case because we look at these as effective constants.
 #[allow(non_snake_case)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MatcherParams {
     pub MAX_CHARACTER_STROKE_COUNT: usize,
     pub MAX_CHARACTER_SUB_STROKE_COUNT: usize,
@@ -55,11 +37,272 @@ impl Default for MatcherParams {
     }
 }
 
+// Scores of templates for another region are multiplied by this when the lookup prefers a region
+pub(crate) const OTHER_REGION_WEIGHT: f32 = 0.85;
+
+// How much a candidate the recognizer's n-gram model never saw after the context loses against the likeliest one, by default
+pub(crate) const DEFAULT_CONTEXT_WEIGHT: f32 = 0.3;
+
+// Per-call lookup settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct LookupOptions {
+    // How far the candidates' stroke and substroke counts may stray from the input's; 0.0 (strict) to 1.0 (anything goes)
+    pub looseness: f32,
+    // If set, lookup reruns with increasing looseness while the best match is weak
+    pub auto_widen: Option<AutoWiden>,
+    // Side of the square canvas the input was drawn on; 256 for Point input
+    pub canvas_size: f32,
+    // If set, templates of the writer's region win over other regions' forms of the same character
+    pub region: Option<RegionFilter>,
+    // If set, only these characters are considered, or all but these
+    pub filter: Option<CharFilter>,
+    // Share of the score that depends on how common the character is, if the recognizer has a frequency table; 0.0 to 1.0.
+    // None leaves scores as they are even then; 0.1 is a good start.
+    pub prior_weight: Option<f32>,
+    // Per-character score multipliers for this call, e.g. 1.2 for characters the context makes likely
+    pub boosts: Option<HashMap<char, f32>>,
+    // Text written before this character; if the recognizer has an n-gram model, candidates are reranked by what likely follows
+    pub context: Option<String>,
+    // Share of the score that depends on the context, 0.0 to 1.0
+    pub context_weight: f32,
+    // Matches with a lower confidence are left out, so that scribbles get no matches at all; 0.6 rejects most of them
+    pub min_confidence: f32,
+    // Input may be just the first strokes of a character: templates with more strokes are scored on their best matching start
+    pub prefix: bool,
+    // Share of the score that ignores stroke order, pairing input and template strokes as sets; 0.0 (strict order) to 1.0
+    pub order_free_weight: f32,
+}
+
+impl LookupOptions {
+    pub fn with_looseness(looseness: f32) -> LookupOptions {
+        LookupOptions {
+            looseness,
+            auto_widen: None,
+            canvas_size: REFERENCE_CANVAS_SIZE,
+            region: None,
+            filter: None,
+            prior_weight: None,
+            boosts: None,
+            context: None,
+            context_weight: DEFAULT_CONTEXT_WEIGHT,
+            min_confidence: 0.0,
+            prefix: false,
+            order_free_weight: 0.0,
+        }
+    }
+}
+
+// Which regional forms lookup considers. A template is for another region if it has a region that's not this one,
+// or if it has none but the same character has a template for this region.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegionFilter {
+    // Templates for other regions score lower
+    Prefer(String),
+    // Templates for other regions are left out
+    Only(String),
+}
+
+// Restricts lookup to a set of characters, e.g. a lesson's vocabulary or a character set like GB2312
+#[derive(Clone, Debug, PartialEq)]
+pub enum CharFilter {
+    // Only these characters can match
+    Allow(HashSet<char>),
+    // These characters never match
+    Deny(HashSet<char>),
+}
+
+impl CharFilter {
+    pub fn allow_str(chars: &str) -> CharFilter {
+        CharFilter::Allow(chars.chars().collect())
+    }
+
+    pub fn deny_str(chars: &str) -> CharFilter {
+        CharFilter::Deny(chars.chars().collect())
+    }
+
+    pub fn allows(&self, hanzi: char) -> bool {
+        match *self {
+            CharFilter::Allow(ref chars) => chars.contains(&hanzi),
+            CharFilter::Deny(ref chars) => !chars.contains(&hanzi),
+        }
+    }
+}
+
+// The options that change how a template is scored, as opposed to which templates are scored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct MatchMode {
+    pub prefix: bool,
+    pub order_free_weight: f32,
+}
+
+impl MatchMode {
+    pub fn strict() -> MatchMode {
+        MatchMode {
+            prefix: false,
+            order_free_weight: 0.0,
+        }
+    }
+
+    pub fn from_options(options: &LookupOptions) -> MatchMode {
+        MatchMode {
+            prefix: options.prefix,
+            order_free_weight: options.order_free_weight,
+        }
+    }
+}
+
+// Everything a lookup goes by besides the input: which templates are compared, how they are scored, how much each one counts,
+// and what earlier work can be reused
+pub(crate) struct LookupContext<'a> {
+    pub looseness: f32,
+    pub mode: MatchMode,
+    pub weights: Option<&'a TemplateWeights>,
+    // Each template's score against itself; computed on the fly if missing
+    pub self_scores: Option<&'a [f32]>,
+    pub cache: Option<&'a mut ScoreCache>,
+}
+
+impl<'a> LookupContext<'a> {
+    pub fn strict(looseness: f32) -> LookupContext<'a> {
+        LookupContext {
+            looseness,
+            mode: MatchMode::strict(),
+            weights: None,
+            self_scores: None,
+            cache: None,
+        }
+    }
+}
+
+impl Default for LookupOptions {
+    fn default() -> LookupOptions {
+        LookupOptions::with_looseness(MatcherParams::default().DEFAULT_LOOSENESS)
+    }
+}
+
+// Settings for rerunning a lookup with a wider window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoWiden {
+    // Widen while the best score is below this, per substroke of the input. A clean match scores around 0.7-0.9.
+    pub min_score: f32,
+    // Looseness added in each round
+    pub step: f32,
+    // Widening stops at this looseness
+    pub max_looseness: f32,
+}
+
+impl Default for AutoWiden {
+    fn default() -> AutoWiden {
+        AutoWiden {
+            min_score: 0.75,
+            step: 0.15,
+            max_looseness: 0.6,
+        }
+    }
+}
+
+// One side of a substroke comparison, at the precision the dataset stores: direction and length as bytes, center on the 16 x 16 grid
+#[derive(Clone, Copy)]
+struct QuantizedSubStroke {
+    direction: u8,
+    length: u8,
+    center: Point,
+}
+
+// DP scratch space, owned by the lookup or its cache; the matcher itself stays immutable so it can be shared across threads.
+// Row-major, in one allocation, so cached matrices are cheap to keep and grow
+#[derive(Clone)]
+struct ScoreMatrix {
+    width: usize,
+    cells: Vec<f32>,
+}
+
+impl ScoreMatrix {
+    fn new(width: usize) -> ScoreMatrix {
+        ScoreMatrix {
+            width,
+            cells: Vec::new(),
+        }
+    }
+
+    fn row_count(&self) -> usize {
+        self.cells.len() / self.width
+    }
+
+    // Adds or drops rows to have exactly row_count; new rows are seeded with the skip penalty in column 0.
+    // Cells of kept rows are not touched: the DP overwrites everything except the seeds.
+    fn resize(&mut self, row_count: usize, penalty: f32) {
+        let old_row_count = self.row_count();
+        self.cells.resize(row_count * self.width, 0f32);
+        for x in old_row_count..row_count {
+            // Row 0 is seeded all along, like column 0
+            if x == 0 {
+                for y in 0..self.width {
+                    self.cells[y] = penalty * (y as f32);
+                }
+            }
+            self.cells[x * self.width] = penalty * (x as f32);
+        }
+    }
+}
+
+impl std::ops::Index<(usize, usize)> for ScoreMatrix {
+    type Output = f32;
+    fn index(&self, (x, y): (usize, usize)) -> &f32 {
+        &self.cells[x * self.width + y]
+    }
+}
+
+impl std::ops::IndexMut<(usize, usize)> for ScoreMatrix {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut f32 {
+        &mut self.cells[x * self.width + y]
+    }
+}
+
+// DP rows kept between lookups of a growing input, per character index in the dataset.
+// Rows are only valid while the input's leading substrokes and the substroke range stay the same.
+pub struct ScoreCache {
+    input_sub_strokes: Vec<SubStroke>,
+    sub_strokes_range: usize,
+    rows: Vec<Option<ScoreMatrix>>,
+    // Input substrokes whose rows the last lookup took over instead of computing them
+    reused_rows: usize,
+}
+
+impl ScoreCache {
+    pub fn new() -> ScoreCache {
+        ScoreCache {
+            input_sub_strokes: Vec::new(),
+            sub_strokes_range: 0,
+            rows: Vec::new(),
+            reused_rows: 0,
+        }
+    }
+
+    pub fn reused_rows(&self) -> usize {
+        self.reused_rows
+    }
+
+    pub fn clear(&mut self) {
+        self.input_sub_strokes.clear();
+        self.rows.clear();
+    }
+
+    // Number of input substrokes whose DP rows can be reused for the new input
+    fn valid_rows(&self, input_sub_strokes: &[SubStroke], sub_strokes_range: usize) -> usize {
+        if sub_strokes_range != self.sub_strokes_range {
+            return 0;
+        }
+        self.input_sub_strokes.iter()
+            .zip(input_sub_strokes.iter())
+            .take_while(|&(a, b)| a == b)
+            .count()
+    }
+}
+
 pub struct Matcher {
     // Magic numbers; can be overridden
     params: MatcherParams,
-    // N*N dimensional matrix where N = MAX_CHARACTER_SUB_STROKE_COUNT + 1
-    score_matrix: Vec<Vec<f32>>,
     // Values pre-computed as solutions of a 2D quadratic curve
     direction_score_table: Vec<f32>,
     // Values pre-computed as solutions of a 2D quadratic curve
@@ -76,93 +319,310 @@ impl Matcher {
 
     pub fn with_params(params: &MatcherParams) -> Matcher {
         let mut res = Matcher {
-            score_matrix: Vec::with_capacity(params.MAX_CHARACTER_SUB_STROKE_COUNT + 1),
             params: *params,
             direction_score_table: Vec::with_capacity(256),
             length_score_table: Vec::with_capacity(129),
             pos_score_table: Vec::with_capacity(450),
         };
         init_score_tables(&mut res.direction_score_table, &mut res.length_score_table, &mut res.pos_score_table);
-        res.init_score_matrix();
         res
     }
 
-    pub fn lookup(&mut self, strokes: &Vec<Stroke>, collector: &mut MatchCollector) {
-        let input_char = AnalyzedCharacter::from_strokes(strokes);
+    pub fn params(&self) -> &MatcherParams {
+        &self.params
+    }
+
+    pub fn lookup(&self, char_data: &[CharData], strokes: &[Stroke], looseness: f32, collector: &mut MatchCollector) {
+        let input_char = AnalyzedCharacter::from_strokes(strokes, REFERENCE_CANVAS_SIZE);
+        self.lookup_analyzed(char_data, &input_char, LookupContext::strict(looseness), collector);
+    }
+
+    // Lookup for input that has already been analyzed; lets callers rerun at a different looseness cheaply.
+    // With a cache in the context, the DP rows kept from the previous call are reused, and the cache is updated.
+    pub fn lookup_analyzed<P: InputPoint>(&self,
+                           char_data: &[CharData],
+                           input_char: &AnalyzedCharacter<P>,
+                           context: LookupContext,
+                           collector: &mut MatchCollector) {
+        let LookupContext { looseness, mode, weights, self_scores, mut cache } = context;
+        let looseness = looseness.clamp(0.0, 1.0);
 
         // Edge case: empty input should return no matches; but permissive lookup does find a few...
         if input_char.analyzed_strokes.len() == 0 {
             return;
         }
 
-        CHAR_DATA.with(|char_data| {
-
-            // Flat format: matching needs this. Only transform once.
-            let input_sub_strokes = input_char.get_analyzed_strokes();
-
-            // Some pre-computed looseness magic
-            let stroke_count = input_char.analyzed_strokes.len();
-            let sub_stroke_count = input_char.sub_stroke_count;
-            // Get the range of strokes to compare against based on the loosness.
-            // Characters with fewer strokes than stroke_count - stroke_range
-            // or more than stroke_count + stroke_range won't even be considered.
-            let stroke_range = self.get_strokes_range(stroke_count, self.params.DEFAULT_LOOSENESS);
-            let minimum_strokes = usize::max(stroke_count - stroke_range, 1);
-            let maximum_strokes = usize::min(stroke_count + stroke_range, self.params.MAX_CHARACTER_STROKE_COUNT);
-            // Get the range of substrokes to compare against based on looseness.
-            // When trying to match sub stroke patterns, won't compare sub strokes
-            // that are farther about in sequence than this range.  This is to make
-            // computing matches less expensive for low loosenesses.
-            let sub_strokes_range = self.get_sub_strokes_range(sub_stroke_count, self.params.DEFAULT_LOOSENESS);
-            let min_sub_strokes = usize::max(sub_stroke_count - sub_strokes_range, 1);
-            let max_sub_strokes = usize::min(sub_stroke_count + sub_strokes_range, self.params.MAX_CHARACTER_SUB_STROKE_COUNT);
-            // Iterate over all characters in repo
-            for cix in 0..char_data.len() {
-                let repo_char = &char_data[cix];
-                let cmp_stroke_count = repo_char.stroke_count;
-                let cmp_sub_strokes = &repo_char.sub_strokes;
-                if (cmp_stroke_count as usize) < minimum_strokes || cmp_stroke_count as usize > maximum_strokes {
-                    continue;
-                }
-                if cmp_sub_strokes.len() < min_sub_strokes || cmp_sub_strokes.len() > max_sub_strokes {
-                    continue;
+        // Flat format: matching needs this. Only transform once.
+        let input_sub_strokes = input_char.get_analyzed_strokes();
+        // Stroke by stroke, for order-free matching
+        let input_strokes: Vec<&[SubStroke]> = input_char.analyzed_strokes.iter().map(|stroke| &stroke.sub_strokes[..]).collect();
+
+        // Some pre-computed looseness magic
+        let stroke_count = input_char.analyzed_strokes.len();
+        let sub_stroke_count = input_char.sub_stroke_count;
+        // Get the range of strokes to compare against based on the loosness.
+        // Characters with fewer strokes than stroke_count - stroke_range
+        // or more than stroke_count + stroke_range won't even be considered.
+        let stroke_range = self.get_strokes_range(stroke_count, looseness);
+        // A prefix can belong to any character with at least as many strokes
+        let minimum_strokes = if mode.prefix { stroke_count } else { usize::max(stroke_count.saturating_sub(stroke_range), 1) };
+        let maximum_strokes = if mode.prefix { self.params.MAX_CHARACTER_STROKE_COUNT } else { usize::min(stroke_count + stroke_range, self.params.MAX_CHARACTER_STROKE_COUNT) };
+        // Get the range of substrokes to compare against based on looseness.
+        // When trying to match sub stroke patterns, won't compare sub strokes
+        // that are farther about in sequence than this range.  This is to make
+        // computing matches less expensive for low loosenesses.
+        let sub_strokes_range = self.get_sub_strokes_range(sub_stroke_count, looseness);
+        let min_sub_strokes = usize::max(sub_stroke_count.saturating_sub(sub_strokes_range), 1);
+        let max_sub_strokes = if mode.prefix { self.params.MAX_CHARACTER_SUB_STROKE_COUNT } else { usize::min(sub_stroke_count + sub_strokes_range, self.params.MAX_CHARACTER_SUB_STROKE_COUNT) };
+
+        // Per-call scratch space for the DP, or the cache's rows that are still valid for this input
+        let mut score_matrix = ScoreMatrix::new(1);
+        let mut valid_rows = 0;
+        let mut old_rows: Vec<Option<ScoreMatrix>> = Vec::new();
+        match cache {
+            Some(ref mut cache) => {
+                valid_rows = cache.valid_rows(&input_sub_strokes, sub_strokes_range);
+                cache.reused_rows = valid_rows;
+                // Rows of characters that drop out of the candidate range are dropped from the cache too
+                old_rows = std::mem::take(&mut cache.rows);
+                old_rows.resize(char_data.len(), None);
+                cache.rows.resize(char_data.len(), None);
+                cache.input_sub_strokes = input_sub_strokes.clone();
+                cache.sub_strokes_range = sub_strokes_range;
+            }
+            None => score_matrix = self.new_score_matrix(),
+        }
+
+        // Iterate over all characters in repo
+        for cix in 0..char_data.len() {
+            let repo_char = &char_data[cix];
+            let weight = match get_weight(weights, cix) {
+                Some(weight) => weight,
+                None => continue,
+            };
+            let cmp_stroke_count = repo_char.stroke_count;
+            let cmp_sub_strokes = &repo_char.sub_strokes;
+            if (cmp_stroke_count as usize) < minimum_strokes || cmp_stroke_count as usize > maximum_strokes {
+                continue;
+            }
+            if cmp_sub_strokes.len() < min_sub_strokes || cmp_sub_strokes.len() > max_sub_strokes {
+                continue;
+            }
+            // Match against character in repo
+            let mut score = match cache {
+                Some(ref mut cache) => {
+                    // Rows beyond the valid ones are simply overwritten
+                    let mut rows = old_rows[cix].take().unwrap_or_else(|| ScoreMatrix::new(cmp_sub_strokes.len() + 1));
+                    let first_row = usize::min(valid_rows, rows.row_count().saturating_sub(1));
+                    rows.resize(input_sub_strokes.len() + 1, self.skip_penalty());
+                    let score = self.match_one(&mut rows, first_row, &input_sub_strokes, sub_strokes_range, mode.prefix, repo_char);
+                    cache.rows[cix] = Some(rows);
+                    score
                 }
-                // Match against character in repo
-                let char_match = self.match_one(stroke_count, &input_sub_strokes, sub_strokes_range, &repo_char);
-                // File; collector takes care of comparisons and keeping N-best
-                collector.file_match(char_match);
+                None => self.match_one(&mut score_matrix, 0, &input_sub_strokes, sub_strokes_range, mode.prefix, repo_char),
+            };
+            if mode.order_free_weight > 0.0 {
+                let order_free_score = self.match_unordered(&input_strokes, mode.prefix, repo_char);
+                score = (1f32 - mode.order_free_weight) * score + mode.order_free_weight * order_free_score;
             }
-       });
+            score += self.get_stroke_count_bonus(stroke_count, repo_char) * score;
+            let mut char_match = Match {
+                hanzi: repo_char.hanzi,
+                score,
+                tag: repo_char.tag.clone(),
+                region: repo_char.region.clone(),
+                confidence: 0.0,
+            };
+            // Confidence is about the drawing alone, so it's taken before weighing in regions, priors and boosts
+            let self_score = match self_scores {
+                Some(self_scores) => self_scores[cix],
+                None => self.self_match_score(repo_char),
+            };
+            char_match.confidence = get_confidence(char_match.score, self_score, input_sub_strokes.len(), cmp_sub_strokes.len());
+            // Lower weights pull the score down whatever its sign
+            char_match.score -= (1f32 - weight) * char_match.score.abs();
+            // File; collector takes care of comparisons and keeping N-best
+            collector.file_match(char_match);
+        }
     }
 
-    fn match_one(   &mut self,
-                    input_stroke_count: usize,
-                    input_sub_strokes: &Vec<SubStroke>,
+    // Score of the input's substrokes aligned with the template's, before the bonus for the right stroke count
+    fn match_one(   &self,
+                    score_matrix: &mut ScoreMatrix,
+                    first_row: usize,
+                    input_sub_strokes: &[SubStroke],
                     sub_strokes_range: usize,
-                    repo_char: &CharData) -> Match {
+                    prefix: bool,
+                    repo_char: &CharData) -> f32 {
         // Calculate score. This is the *actual* meat.
-        let mut score = self.compute_match_score(input_sub_strokes, sub_strokes_range, repo_char);
-        // If the input character and the character in the repository have the same number of strokes, assign a small bonus.
-        // Might be able to remove this, doesn't really add much, only semi-useful for characters with only a couple strokes.
+        let score = self.compute_match_score(score_matrix, first_row, input_sub_strokes, sub_strokes_range, repo_char);
+        // For a prefix, the template's trailing substrokes that the input hasn't reached yet cost nothing
+        if prefix {
+            let prefix_end = get_prefix_end(score_matrix, input_sub_strokes.len(), repo_char.sub_strokes.len());
+            return score_matrix[(input_sub_strokes.len(), prefix_end)];
+        }
+        score
+    }
+
+    // Best score of the input aligned anywhere inside the template: skipping template substrokes before and after it is free
+    pub(crate) fn match_component(&self, input_sub_strokes: &[SubStroke], repo_char: &CharData) -> f32 {
+        let template_length = repo_char.sub_strokes.len();
+        let mut score_matrix = ScoreMatrix::new(template_length + 1);
+        score_matrix.resize(input_sub_strokes.len() + 1, self.skip_penalty());
+        for y in 0..template_length + 1 {
+            score_matrix[(0, y)] = 0f32;
+        }
+        self.compute_match_score(&mut score_matrix, 0, input_sub_strokes, input_sub_strokes.len() + template_length, repo_char);
+        let component_end = get_prefix_end(&score_matrix, input_sub_strokes.len(), template_length);
+        score_matrix[(input_sub_strokes.len(), component_end)]
+    }
+
+    // If the input character and the character in the repository have the same number of strokes, assign a small bonus.
+    // Might be able to remove this, doesn't really add much, only semi-useful for characters with only a couple strokes.
+    fn get_stroke_count_bonus(&self, input_stroke_count: usize, repo_char: &CharData) -> f32 {
         if input_stroke_count == repo_char.stroke_count as usize && input_stroke_count < self.params.CORRECT_NUM_STROKES_CAP {
             // The bonus declines linearly as the number of strokes increases, writing 2 instead of 3 strokes is worse than 9 for 10.
-            let bonus = self.params.CORRECT_NUM_STROKES_BONUS * 
-                (i32::max(self.params.CORRECT_NUM_STROKES_CAP as i32 - input_stroke_count as i32, 0) as f32) / 
-                (self.params.CORRECT_NUM_STROKES_CAP as f32);
-            score += bonus * score;
+            self.params.CORRECT_NUM_STROKES_BONUS *
+                (i32::max(self.params.CORRECT_NUM_STROKES_CAP as i32 - input_stroke_count as i32, 0) as f32) /
+                (self.params.CORRECT_NUM_STROKES_CAP as f32)
+        }
+        else {
+            0f32
+        }
+    }
+
+    // Score of the input's strokes paired up with the template's as sets, in whatever order they were written.
+    // Pairs are taken greedily, most similar first; each adds its similarity for every substroke of the template stroke,
+    // about what the pair adds to the ordered score. Unpaired strokes cost their skip penalties, except the template's for a prefix.
+    pub(crate) fn match_unordered(&self, input_strokes: &[&[SubStroke]], prefix: bool, repo_char: &CharData) -> f32 {
+        let template_ranges = repo_char.stroke_ranges();
+        let mut candidates: Vec<(f32, usize, usize)> = Vec::with_capacity(input_strokes.len() * template_ranges.len());
+        for (i, input_stroke) in input_strokes.iter().enumerate() {
+            for (t, &(start, end)) in template_ranges.iter().enumerate() {
+                let similarity = self.compare_stroke(input_stroke, &repo_char.sub_strokes[start..end]);
+                if similarity > 0.0 {
+                    candidates.push((similarity, i, t));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        let mut input_paired = vec![false; input_strokes.len()];
+        let mut template_paired = vec![false; template_ranges.len()];
+        let mut score = 0f32;
+        for (similarity, i, t) in candidates {
+            if !input_paired[i] && !template_paired[t] {
+                input_paired[i] = true;
+                template_paired[t] = true;
+                score += similarity * (template_ranges[t].1 - template_ranges[t].0) as f32;
+            }
+        }
+        for (input_stroke, _) in input_strokes.iter().zip(input_paired.iter()).filter(|&(_, paired)| !paired) {
+            score -= input_stroke.iter().map(|sub_stroke| sub_stroke.length.round() as u8 as f32 / 256.0 * self.params.SKIP_PENALTY_MULTIPLIER).sum::<f32>();
         }
-        Match {
+        if !prefix {
+            for (&(start, end), _) in template_ranges.iter().zip(template_paired.iter()).filter(|&(_, paired)| !paired) {
+                score -= repo_char.sub_strokes[start..end].iter().map(|sub_stroke| sub_stroke.length as f32 / 256.0 * self.params.SKIP_PENALTY_MULTIPLIER).sum::<f32>();
+            }
+        }
+        score
+    }
+
+    // Score of a template matched against its own substrokes: what a perfect drawing of the character would get
+    pub(crate) fn self_match_score(&self, repo_char: &CharData) -> f32 {
+        let sub_strokes: Vec<SubStroke> = repo_char.sub_strokes.iter().map(|sub_stroke| SubStroke {
+            direction: sub_stroke.dir as f32,
+            length: sub_stroke.length as f32,
+            center_x: (sub_stroke.center >> 4) as f32,
+            center_y: (sub_stroke.center & 0x0f) as f32,
+        }).collect();
+        let mut score_matrix = ScoreMatrix::new(sub_strokes.len() + 1);
+        score_matrix.resize(sub_strokes.len() + 1, self.skip_penalty());
+        let score = self.match_one(&mut score_matrix, 0, &sub_strokes, 0, false, repo_char);
+        score + self.get_stroke_count_bonus(repo_char.stroke_count as usize, repo_char) * score
+    }
+
+    // Scores the input against one character the same way lookup does, then traces the alignment behind the score back
+    // through the DP matrix. The substroke band is widened if needed, so that characters lookup would not consider still align.
+    // For a prefix, the template substrokes after the input's end are listed as skipped at no cost.
+    pub fn explain<P: InputPoint>(&self, input_char: &AnalyzedCharacter<P>, looseness: f32, prefix: bool, repo_char: &CharData) -> Explanation {
+        let looseness = looseness.clamp(0.0, 1.0);
+        let input_sub_strokes = input_char.get_analyzed_strokes();
+        // A prefix is expected to be shorter than the template, and ends wherever it fits best
+        let size_difference = if prefix { 0 } else { ((input_sub_strokes.len() as i32) - (repo_char.sub_strokes.len() as i32)).unsigned_abs() as usize };
+        let sub_strokes_range = usize::max(self.get_sub_strokes_range(input_char.sub_stroke_count, looseness), size_difference);
+        let mut score_matrix = ScoreMatrix::new(repo_char.sub_strokes.len() + 1);
+        score_matrix.resize(input_sub_strokes.len() + 1, self.skip_penalty());
+        let score = self.match_one(&mut score_matrix, 0, &input_sub_strokes, sub_strokes_range, prefix, repo_char);
+        let score = score + self.get_stroke_count_bonus(input_char.analyzed_strokes.len(), repo_char) * score;
+
+        // Walk back from the corner, or from the prefix's end, redoing each cell's choice between matching and skipping
+        let mut steps: Vec<AlignmentStep> = Vec::new();
+        let mut x = input_sub_strokes.len();
+        let mut y = repo_char.sub_strokes.len();
+        if prefix {
+            let prefix_end = get_prefix_end(&score_matrix, x, y);
+            while y > prefix_end {
+                y -= 1;
+                steps.push(AlignmentStep::SkippedTemplate { template: y, penalty: 0.0 });
+            }
+        }
+        while x > 0 || y > 0 {
+            // Seeded row and column: whatever is left on the other side was skipped at the start
+            if y == 0 {
+                x -= 1;
+                steps.push(AlignmentStep::SkippedInput { input: x, penalty: -self.skip_penalty() });
+                continue;
+            }
+            if x == 0 {
+                y -= 1;
+                steps.push(AlignmentStep::SkippedTemplate { template: y, penalty: -self.skip_penalty() });
+                continue;
+            }
+            let input_penalty = input_sub_strokes[x - 1].length.round() as u8 as f32 / 256.0 * self.params.SKIP_PENALTY_MULTIPLIER;
+            let template_penalty = repo_char.sub_strokes[y - 1].length as f32 / 256.0 * self.params.SKIP_PENALTY_MULTIPLIER;
+            let skip_input_score = score_matrix[(x - 1, y)] - input_penalty;
+            let skip_template_score = score_matrix[(x, y - 1)] - template_penalty;
+            let scores = self.compute_pair_scores(&input_sub_strokes[x - 1], &repo_char.sub_strokes[y - 1], true);
+            if score_matrix[(x - 1, y - 1)] + scores.total >= f32::max(skip_input_score, skip_template_score) {
+                x -= 1;
+                y -= 1;
+                steps.push(AlignmentStep::Matched { input: x, template: y, scores });
+            }
+            else if skip_input_score >= skip_template_score {
+                x -= 1;
+                steps.push(AlignmentStep::SkippedInput { input: x, penalty: input_penalty });
+            }
+            else {
+                y -= 1;
+                steps.push(AlignmentStep::SkippedTemplate { template: y, penalty: template_penalty });
+            }
+        }
+        steps.reverse();
+
+        let mut input_strokes: Vec<usize> = Vec::with_capacity(input_sub_strokes.len());
+        for analyzed_stroke in &input_char.analyzed_strokes {
+            for _ in 0..analyzed_stroke.sub_strokes.len() {
+                input_strokes.push(analyzed_stroke.index);
+            }
+        }
+        Explanation {
             hanzi: repo_char.hanzi,
-            score: score,
+            tag: repo_char.tag.clone(),
+            region: repo_char.region.clone(),
+            score,
+            input_strokes,
+            steps,
         }
     }
 
-    fn compute_match_score( &mut self,
-                            input_sub_strokes: &Vec<SubStroke>,
+    // Fills the DP rows below first_row; rows up to and including first_row must already hold valid scores
+    fn compute_match_score( &self,
+                            score_matrix: &mut ScoreMatrix,
+                            first_row: usize,
+                            input_sub_strokes: &[SubStroke],
                             sub_strokes_range: usize,
                             repo_char: &CharData) -> f32 {
-        // 
-        for x in 0..input_sub_strokes.len() {
+        for x in first_row..input_sub_strokes.len() {
             // For each of the input substrokes...
             let input_direction = input_sub_strokes[x].direction.round() as u8;
             let input_length = input_sub_strokes[x].length.round() as u8;
@@ -187,9 +647,9 @@ impl Matcher {
                     };
                     // We incur penalties for skipping substrokes.
                     // Get the scores that would be incurred either for skipping the substroke from the descriptor, or from the repository.
-                    let skip1_score = self.score_matrix[x][y + 1] -
+                    let skip1_score = score_matrix[(x, y + 1)] -
                         (input_length as f32 / 256.0 * self.params.SKIP_PENALTY_MULTIPLIER);
-                    let skip2_score = self.score_matrix[x + 1][y] - 
+                    let skip2_score = score_matrix[(x + 1, y)] - 
                         (cmp_length as f32 / 256.0 * self.params.SKIP_PENALTY_MULTIPLIER);
                     // The skip score is the maximum of the scores that would result from skipping one of the substrokes.
                     let skip_score = f32::max(skip1_score, skip2_score);
@@ -201,17 +661,17 @@ impl Matcher {
                         input_center, 
                         cmp_center);
                     // Previous score is the score we'd add to if we compared the two substrokes.
-                    let prev_score = self.score_matrix[x][y];
+                    let prev_score = score_matrix[(x, y)];
                     // Result score is the maximum of skipping a substroke, or comparing the two.
                     new_score = f32::max(prev_score + match_score, skip_score);
                 }
                 // Set the score for comparing the two substrokes.
-                self.score_matrix[x + 1][y + 1] = new_score;
+                score_matrix[(x + 1, y + 1)] = new_score;
             }
         }
         // At the end the score is the score at the opposite corner of the matrix...
         // don't need to use count - 1 since seed values occupy indices 0
-        self.score_matrix[input_sub_strokes.len()][repo_char.sub_strokes.len()]
+        score_matrix[(input_sub_strokes.len(), repo_char.sub_strokes.len())]
     }
 
     fn compute_sub_stroke_score(&self,
@@ -221,15 +681,22 @@ impl Matcher {
                                 repo_length: u8, 
                                 input_center: Point,
                                 repo_center: Point) -> f32 {
+        let input = QuantizedSubStroke { direction: input_direction, length: input_length, center: input_center };
+        let repo = QuantizedSubStroke { direction: repo_direction, length: repo_length, center: repo_center };
+        self.compute_sub_stroke_scores(input, repo, true).total
+    }
+
+    // The substroke score together with the parts it is made of
+    fn compute_sub_stroke_scores(&self, input: QuantizedSubStroke, repo: QuantizedSubStroke, short_length_bonus: bool) -> SubStrokeScores {
         // Score drops off after directions get sufficiently apart, start to rise again as the substrokes approach opposite directions.
         // This in particular reflects that occasionally strokes will be written backwards, this isn't totally bad, they get
         // some score for having the stroke oriented correctly.
-        let direction_score = self.get_direction_score(input_direction, repo_direction, input_length);
+        let direction_score = self.get_direction_score(input.direction, repo.direction, input.length, short_length_bonus);
         //var direction_score = Math.max(Math.cos(2.0 * theta), 0.3 * Math.cos((1.5 * theta) + (Math.PI / 3.0)));
 
         // Length score gives an indication of how similar the lengths of the substrokes are.
         // Get the ratio of the smaller of the lengths over the longer of the lengths.
-        let length_score = self.get_length_score(input_length, repo_length);
+        let length_score = self.get_length_score(input.length, repo.length);
         // Ratios that are within a certain range are fine, but after that they drop off, scores not more than 1.
         //var length_score = Math.log(length_score + (1.0 / Math.E)) + 1;
         //length_score = Math.min(length_score, 1.0);
@@ -238,8 +705,8 @@ impl Matcher {
         let mut score = length_score * direction_score;
 
         // Reduce score if strokes are farther apart
-        let dx = input_center.x as i32 - repo_center.x as i32;
-        let dy = input_center.y as i32 - repo_center.y as i32;
+        let dx = input.center.x as i32 - repo.center.x as i32;
+        let dy = input.center.y as i32 - repo.center.y as i32;
         let closeness = self.pos_score_table[(dx * dx + dy * dy) as usize];
 
         // var dist = Math.sqrt(dx * dx + dy * dy);
@@ -252,17 +719,82 @@ impl Matcher {
         else { score /= closeness; }
         
         // Done
-        score
+        SubStrokeScores {
+            direction: direction_score,
+            length: length_score,
+            position: closeness,
+            total: score,
+        }
+    }
+
+    // Similarity of one stroke's substrokes to another's, aligned with the same scores and skip penalties as whole characters,
+    // except that skips at the start also cost by length: a stroke's short tick is as easy to drop at its start as at its end.
+    // Direction counts for short substrokes too; judging a single stroke, there is no rest of the character to make up for it.
+    // Divided by the shorter side's substroke count, the most pairs there can be, so a clean match scores close to 1.
+    pub(crate) fn compare_stroke(&self, input: &[SubStroke], template: &[SubStrokeTriple]) -> f32 {
+        if input.is_empty() || template.is_empty() {
+            return 0f32;
+        }
+        let input_penalties: Vec<f32> = input.iter()
+            .map(|sub_stroke| sub_stroke.length.round() as u8 as f32 / 256.0 * self.params.SKIP_PENALTY_MULTIPLIER)
+            .collect();
+        let template_penalties: Vec<f32> = template.iter()
+            .map(|sub_stroke| sub_stroke.length as f32 / 256.0 * self.params.SKIP_PENALTY_MULTIPLIER)
+            .collect();
+        let mut score_matrix = ScoreMatrix::new(template.len() + 1);
+        score_matrix.resize(input.len() + 1, 0f32);
+        for x in 0..input.len() {
+            score_matrix[(x + 1, 0)] = score_matrix[(x, 0)] - input_penalties[x];
+        }
+        for y in 0..template.len() {
+            score_matrix[(0, y + 1)] = score_matrix[(0, y)] - template_penalties[y];
+        }
+        for x in 0..input.len() {
+            for y in 0..template.len() {
+                let skip1_score = score_matrix[(x, y + 1)] - input_penalties[x];
+                let skip2_score = score_matrix[(x + 1, y)] - template_penalties[y];
+                let match_score = self.compute_pair_scores(&input[x], &template[y], false).total;
+                score_matrix[(x + 1, y + 1)] = f32::max(score_matrix[(x, y)] + match_score, f32::max(skip1_score, skip2_score));
+            }
+        }
+        score_matrix[(input.len(), template.len())] / usize::min(input.len(), template.len()) as f32
+    }
+
+    // Closeness of two points on the 16 x 16 grid of substroke centers, the same way substroke scores measure it
+    pub(crate) fn position_score(&self, dx: f32, dy: f32) -> f32 {
+        let dist_squared = (dx * dx + dy * dy).round() as usize;
+        self.pos_score_table[usize::min(dist_squared, self.pos_score_table.len() - 1)]
     }
 
-    fn get_direction_score(&self, direction1: u8, direction2: u8, input_length: u8) -> f32 {
+    // Same comparison as in the DP loop, from an analyzed input substroke and a template substroke
+    fn compute_pair_scores(&self, input_sub_stroke: &SubStroke, repo_sub_stroke: &SubStrokeTriple, short_length_bonus: bool) -> SubStrokeScores {
+        let input = QuantizedSubStroke {
+            direction: input_sub_stroke.direction.round() as u8,
+            length: input_sub_stroke.length.round() as u8,
+            center: Point {
+                x: input_sub_stroke.center_x as u8,
+                y: input_sub_stroke.center_y as u8,
+            },
+        };
+        let repo = QuantizedSubStroke {
+            direction: repo_sub_stroke.dir,
+            length: repo_sub_stroke.length,
+            center: Point {
+                x: (repo_sub_stroke.center & 0xf0).wrapping_shr(4),
+                y: repo_sub_stroke.center & 0x0f,
+            },
+        };
+        self.compute_sub_stroke_scores(input, repo, short_length_bonus)
+    }
+
+    fn get_direction_score(&self, direction1: u8, direction2: u8, input_length: u8, short_length_bonus: bool) -> f32 {
         // Both directions are [0..255], integer
         let theta = (direction1 as i32 - direction2 as i32).abs() as usize;
         // Lookup table for actual score function
         let mut direction_score = self.direction_score_table[theta];
         // Add bonus if the input length is small.
         // Directions doesn't really matter for small dian-like strokes.
-        if input_length < 64 {
+        if short_length_bonus && input_length < 64 {
             let short_length_bonus_max = f32::min(1.0, 1.0 - direction_score);
             let short_length_bonus = short_length_bonus_max * (1.0 - (input_length as f32 / 64.0));
             direction_score += short_length_bonus;
@@ -280,29 +812,18 @@ impl Matcher {
         self.length_score_table[ratio]
     }
 
-    fn init_score_matrix(&mut self) {
-        // Allocate if this is the first time we're initializing
-        if self.score_matrix.len() == 0 {
-            for i in 0..self.params.MAX_CHARACTER_SUB_STROKE_COUNT + 1 {
-                self.score_matrix.push(Vec::with_capacity(self.params.MAX_CHARACTER_SUB_STROKE_COUNT + 1));
-                for _ in 0..self.params.MAX_CHARACTER_SUB_STROKE_COUNT + 1 {
-                    self.score_matrix[i].push(0f32);
-                }
-            }
-        }
-        // For starters, everythig is zero
-        for i in 0..self.score_matrix.len() {
-            for j in 0..self.score_matrix[i].len() {
-                self.score_matrix[i][j] = 0f32;
-            }
-        }
+    // Penalty for each substroke skipped at the start of a match
+    fn skip_penalty(&self) -> f32 {
+        -self.params.AVG_SUBSTROKE_LENGTH * self.params.SKIP_PENALTY_MULTIPLIER
+    }
+
+    fn new_score_matrix(&self) -> ScoreMatrix {
         // Seed the first row and column with base values.
         // Starting from a cell that isn't at 0,0 to skip strokes incurs a penalty.
-        for i in 0..self.score_matrix.len() {
-            let penalty = -self.params.AVG_SUBSTROKE_LENGTH * self.params.SKIP_PENALTY_MULTIPLIER * (i as f32);
-            self.score_matrix[i][0] = penalty;
-            self.score_matrix[0][i] = penalty;
-        }
+        let size = self.params.MAX_CHARACTER_SUB_STROKE_COUNT + 1;
+        let mut score_matrix = ScoreMatrix::new(size);
+        score_matrix.resize(size, self.skip_penalty());
+        score_matrix
     }
 
     fn get_strokes_range(&self, stroke_count: usize, looseness: f32) -> usize {
@@ -342,6 +863,85 @@ impl Matcher {
 }
 
 
+// Template substroke count where the input's alignment scores best, if the rest of the template is left for later strokes
+fn get_prefix_end(score_matrix: &ScoreMatrix, input_sub_stroke_count: usize, template_sub_stroke_count: usize) -> usize {
+    let mut best = template_sub_stroke_count;
+    for y in (0..template_sub_stroke_count).rev() {
+        if score_matrix[(input_sub_stroke_count, y)] > score_matrix[(input_sub_stroke_count, best)] {
+            best = y;
+        }
+    }
+    best
+}
+
+// Share of the template's self-match score that a match reached, clamped to 0..1.
+// Input with fewer substrokes than the template can only score for as many, e.g. if it leaves out the short joints between strokes.
+pub(crate) fn get_confidence(score: f32, self_score: f32, input_sub_stroke_count: usize, template_sub_stroke_count: usize) -> f32 {
+    let reachable = self_score * f32::min(1.0, input_sub_stroke_count as f32 / template_sub_stroke_count as f32);
+    if reachable <= 0.0 {
+        return 0.0;
+    }
+    (score / reachable).clamp(0.0, 1.0)
+}
+
+// How lookup adjusts each template's score, in dataset order: None leaves the template out, Some(weight) scales it
+pub(crate) type TemplateWeights = Vec<Option<f32>>;
+
+// Weight of the template at cix; None if it's left out
+pub(crate) fn get_weight(weights: Option<&TemplateWeights>, cix: usize) -> Option<f32> {
+    match weights {
+        Some(weights) => weights[cix],
+        None => Some(1f32),
+    }
+}
+
+// None if the options and the frequency prior don't favor any templates over others
+pub(crate) fn get_template_weights(char_data: &[CharData], prior: Option<&FrequencyPrior>, options: &LookupOptions) -> Option<TemplateWeights> {
+    let prior = match (prior, options.prior_weight) {
+        (Some(prior), Some(prior_weight)) if prior_weight > 0.0 => Some((prior, prior_weight)),
+        _ => None,
+    };
+    if options.region.is_none() && options.filter.is_none() && prior.is_none() && options.boosts.is_none() {
+        return None;
+    }
+    let region_weights = get_region_weights(char_data, options.region.as_ref());
+    Some(char_data.iter().enumerate().map(|(cix, repo_char)| {
+        if let Some(ref filter) = options.filter {
+            if !filter.allows(repo_char.hanzi) {
+                return None;
+            }
+        }
+        let mut weight = match region_weights {
+            Some(ref region_weights) => region_weights[cix]?,
+            None => 1f32,
+        };
+        if let Some((prior, prior_weight)) = prior {
+            weight *= 1f32 - prior_weight * (1f32 - prior.prior(repo_char.hanzi));
+        }
+        if let Some(ref boosts) = options.boosts {
+            weight *= *boosts.get(&repo_char.hanzi).unwrap_or(&1f32);
+        }
+        Some(weight)
+    }).collect())
+}
+
+fn get_region_weights(char_data: &[CharData], region_filter: Option<&RegionFilter>) -> Option<TemplateWeights> {
+    let (region, other_weight) = match region_filter {
+        Some(RegionFilter::Prefer(region)) => (region, Some(OTHER_REGION_WEIGHT)),
+        Some(RegionFilter::Only(region)) => (region, None),
+        None => return None,
+    };
+    let in_region = |repo_char: &CharData| repo_char.region.as_ref() == Some(region);
+    let regional: HashSet<char> = char_data.iter().filter(|repo_char| in_region(repo_char)).map(|repo_char| repo_char.hanzi).collect();
+    Some(char_data.iter().map(|repo_char| {
+        let other_region = match repo_char.region {
+            Some(_) => !in_region(repo_char),
+            None => regional.contains(&repo_char.hanzi),
+        };
+        if other_region { other_weight } else { Some(1f32) }
+    }).collect())
+}
+
 fn init_score_tables(direction_score_table: &mut Vec<f32>, length_score_table: &mut Vec<f32>, pos_score_table: &mut Vec<f32>) {
     // Builds a precomputed array of values to use when getting the score between two substroke directions.
     // Two directions should differ by 0 - Pi, and the score should be the (difference / Pi) * score table's length
@@ -385,6 +985,7 @@ mod tests {
     use std::fmt::Write;
     use std::time::{Instant};
     use super::*;
+    use super::super::test_samples::*;
 
     #[test]
     fn test_score_tables() {
@@ -414,44 +1015,18 @@ mod tests {
         assert!(pos_score_table[449] < 0.04);
     }
 
-    // These manual samples are custom-saved from a tweaked version of the HanziLookupJS demo
-    // This is a hand-drawn 一
-    static STROKES_1: &str = "[[[70,124],[71,124],[79,124],[104,124],[119,124],[132,125],[151,126],[168,126],[169,126],[189,125],[191,124],[191,124]]]";
-    // This is a hand-drawn 十
-    static STROKES_2: &str = "[[[76,127],[77,127],[84,127],[97,128],[119,128],[125,129],[138,130],[147,130],[153,131],[154,131],[158,131],[162,131],[167,131],[168,131],[169,131],[169,131]],[[129,60],[129,62],[128,74],[128,102],[128,118],[129,143],[130,162],[130,170],[130,178],[131,184],[131,188],[131,193],[131,196],[131,198],[131,203],[131,203]]]";
-    // This is a hand-drawn 元
-    static STROKES_3: &str = "[[[86,65],[98,66],[146,69],[152,69],[161,69],[166,69],[170,68],[170,68]],[[47,97],[48,97],[54,97],[89,103],[117,104],[146,101],[169,100],[176,98],[180,98],[184,98],[189,98],[193,98],[195,98],[195,98]],[[103,109],[103,110],[99,132],[91,156],[70,180],[56,190],[53,192]],[[143,105],[143,106],[142,114],[140,134],[138,149],[138,160],[138,167],[140,174],[144,182],[150,186],[155,190],[161,193],[166,194],[172,196],[188,197],[193,197],[197,197],[206,197],[206,196],[207,196],[208,196],[208,194],[204,182],[203,174],[202,174],[202,175],[202,176]]]";
-    // This is a hand-drawn 氣
-    static STROKES_4: &str = "[[[76,32],[76,33],[75,37],[73,43],[70,51],[67,58],[64,66],[61,72],[57,77],[52,82],[50,85],[50,85]],[[68,58],[69,58],[76,58],[90,59],[100,60],[110,62],[118,62],[132,62],[136,62],[141,62],[145,62],[146,62],[148,62],[148,62]],[[68,95],[69,95],[77,96],[96,96],[105,96],[110,96],[126,97],[144,98],[146,98],[154,98],[156,98],[156,98]],[[59,126],[60,126],[67,126],[90,130],[107,131],[120,132],[134,132],[149,132],[151,132],[156,132],[158,133],[158,134],[156,142],[154,147],[153,155],[152,160],[151,166],[150,172],[150,179],[150,183],[150,186],[150,190],[151,194],[152,199],[156,204],[158,206],[162,209],[167,213],[171,215],[175,216],[184,220],[192,222],[196,223],[200,224],[204,225],[208,225],[210,225],[214,225],[218,223],[218,222],[216,214],[214,208],[214,207],[214,207]],[[79,147],[82,148],[87,155],[91,161],[91,161]],[[124,148],[123,148],[116,155],[110,162],[108,164],[108,164]],[[73,175],[75,175],[88,178],[98,180],[104,180],[111,182],[117,182],[122,182],[125,182]],[[100,148],[100,151],[102,172],[102,195],[103,204],[103,211],[104,216],[104,220],[104,224]],[[94,189],[93,189],[81,204],[72,210],[71,210]],[[109,192],[112,194],[120,199],[132,208],[133,210],[133,210]]]";
-
-    fn parse_sample(str_strokes: &str) -> Vec<Stroke> {
-        let vec_strokes: Vec<Vec<Vec<u8>>> = serde_json::from_str(str_strokes).unwrap();
-        let mut strokes: Vec<Stroke> = Vec::new();
-        for vec_stroke in &vec_strokes {
-            let mut points: Vec<Point> = Vec::new();
-            for vec_point in vec_stroke {
-                points.push(Point {
-                    x: vec_point[0],
-                    y: vec_point[1],
-                });
-            }
-            strokes.push(Stroke {
-                points: points,
-            });
-        }
-        strokes
-    }
-
     #[test]
+    #[cfg(feature = "embedded-data")]
     fn test_matches() {
         let mut barf = String::new();
-        let mut matcher = Matcher::new();
+        let matcher = Matcher::new();
+        let char_data = Dataset::embedded().chars;
         let mut res: Vec<Match> = Vec::with_capacity(8);
         {
             let sample = parse_sample(STROKES_1);
             res.clear();
             let mut collector = MatchCollector::new(&mut res, 8);
-            matcher.lookup(&sample, &mut collector);
+            matcher.lookup(&char_data, &sample, matcher.params().DEFAULT_LOOSENESS, &mut collector);
             //write!(&mut barf, "#1: {}", res[0].hanzi).unwrap();
             assert!(res[0].hanzi == '一');
         }
@@ -459,7 +1034,7 @@ mod tests {
             let sample = parse_sample(STROKES_2);
             res.clear();
             let mut collector = MatchCollector::new(&mut res, 8);
-            matcher.lookup(&sample, &mut collector);
+            matcher.lookup(&char_data, &sample, matcher.params().DEFAULT_LOOSENESS, &mut collector);
             //write!(&mut barf, "#1: {}  #2: {}  #3: {}  #4: {}", res[0].hanzi, res[1].hanzi, res[2].hanzi, res[3].hanzi).unwrap();
             assert!(res[0].hanzi == '十');
         }
@@ -467,7 +1042,7 @@ mod tests {
             let sample = parse_sample(STROKES_3);
             res.clear();
             let mut collector = MatchCollector::new(&mut res, 8);
-            matcher.lookup(&sample, &mut collector);
+            matcher.lookup(&char_data, &sample, matcher.params().DEFAULT_LOOSENESS, &mut collector);
             //write!(&mut barf, "#1: {}  #2: {}  #3: {}  #4: {}", res[0].hanzi, res[1].hanzi, res[2].hanzi, res[3].hanzi).unwrap();
             assert!(res[1].hanzi == '元'); // Here we get the right char as the second match!
         }
@@ -476,7 +1051,7 @@ mod tests {
             res.clear();
             let start = Instant::now();
             let mut collector = MatchCollector::new(&mut res, 8);
-            matcher.lookup(&sample, &mut collector);
+            matcher.lookup(&char_data, &sample, matcher.params().DEFAULT_LOOSENESS, &mut collector);
             let duration = start.elapsed();
             write!(&mut barf, "Duration: {:?}", duration).unwrap();
             println!("Duration: {:?}", duration);
@@ -484,4 +1059,40 @@ mod tests {
             assert!(res[0].hanzi == '氣');
         }
     }
+
+    #[test]
+    fn test_template_weights() {
+        let regional = |hanzi: char, region: Option<&str>| {
+            let mut char_data = CharData::from_strokes(hanzi, Vec::new()).unwrap();
+            char_data.region = region.map(|region| region.to_string());
+            char_data
+        };
+        let chars = vec![regional('户', None), regional('户', Some("TW")), regional('十', None), regional('骨', Some("JP"))];
+        let mut options = LookupOptions::default();
+        assert_eq!(get_template_weights(&chars, None, &options), None);
+        options.region = Some(RegionFilter::Prefer("TW".to_string()));
+        assert_eq!(get_template_weights(&chars, None, &options), Some(vec![Some(OTHER_REGION_WEIGHT), Some(1.0), Some(1.0), Some(OTHER_REGION_WEIGHT)]));
+        options.region = Some(RegionFilter::Only("TW".to_string()));
+        assert_eq!(get_template_weights(&chars, None, &options), Some(vec![None, Some(1.0), Some(1.0), None]));
+        options.region = Some(RegionFilter::Only("JP".to_string()));
+        assert_eq!(get_template_weights(&chars, None, &options), Some(vec![Some(1.0), None, Some(1.0), Some(1.0)]));
+        // The character filter applies on top of the region
+        options.filter = Some(CharFilter::deny_str("十"));
+        assert_eq!(get_template_weights(&chars, None, &options), Some(vec![Some(1.0), None, None, Some(1.0)]));
+        options.region = None;
+        options.filter = Some(CharFilter::allow_str("户骨"));
+        assert_eq!(get_template_weights(&chars, None, &options), Some(vec![Some(1.0), Some(1.0), None, Some(1.0)]));
+        // Frequency prior and boosts scale what's left
+        let prior = FrequencyPrior::parse("十\t9999\n").unwrap();
+        options.filter = Some(CharFilter::deny_str("骨"));
+        let mut boosts = HashMap::new();
+        boosts.insert('户', 3.0);
+        options.boosts = Some(boosts);
+        options.prior_weight = Some(0.5);
+        assert_eq!(get_template_weights(&chars, Some(&prior), &options), Some(vec![Some(1.5), Some(1.5), Some(1.0), None]));
+        options.prior_weight = Some(0.0);
+        options.boosts = None;
+        options.filter = None;
+        assert_eq!(get_template_weights(&chars, Some(&prior), &options), None);
+    }
 }
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use super::error::*;
+use super::*;
+
+// Share of the context probability that's kept when falling back from trigrams to bigrams ("stupid backoff")
+const BACKOFF_FACTOR: f64 = 0.4;
+
+// Character bigram and trigram counts, for reranking candidates by the text that precedes them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NgramModel {
+    bigrams: HashMap<(char, char), u64>,
+    trigrams: HashMap<(char, char, char), u64>,
+    // Sum of the counts of all bigrams starting with a character, and of all trigrams starting with a pair
+    bigram_totals: HashMap<char, u64>,
+    trigram_totals: HashMap<(char, char), u64>,
+}
+
+impl NgramModel {
+    pub fn new() -> NgramModel {
+        NgramModel::default()
+    }
+
+    // Parses a table with one bigram or trigram and its count per line, separated by a tab.
+    // Empty lines and lines starting with # are skipped; an n-gram listed twice has its counts added up.
+    pub fn parse(text: &str) -> Result<NgramModel, Error> {
+        let mut model = NgramModel::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let ngram: Vec<char> = fields.next().unwrap_or("").chars().collect();
+            let count: u64 = match (fields.next().map(|field| field.trim().parse()), fields.next()) {
+                (Some(Ok(count)), None) => count,
+                _ => return Err(Error::InvalidNgramTable { line: i + 1 }),
+            };
+            if !model.add(&ngram, count) {
+                return Err(Error::InvalidNgramTable { line: i + 1 });
+            }
+        }
+        Ok(model)
+    }
+
+    // Adds count occurrences of a two or three character sequence; false for any other length
+    pub fn add(&mut self, ngram: &[char], count: u64) -> bool {
+        match *ngram {
+            [first, second] => {
+                *self.bigrams.entry((first, second)).or_insert(0) += count;
+                *self.bigram_totals.entry(first).or_insert(0) += count;
+            }
+            [first, second, third] => {
+                *self.trigrams.entry((first, second, third)).or_insert(0) += count;
+                *self.trigram_totals.entry((first, second)).or_insert(0) += count;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // How likely hanzi is to follow context, from the last one or two characters of it; 0.0 if the counts don't say
+    pub fn probability(&self, context: &str, hanzi: char) -> f64 {
+        let mut last_chars = context.chars().rev();
+        let prev = match last_chars.next() {
+            Some(prev) => prev,
+            None => return 0.0,
+        };
+        // Back off to the bigram if the trigram context is known, but not followed by hanzi
+        let mut backoff = 1.0;
+        if let Some(prev2) = last_chars.next() {
+            if let Some(&total) = self.trigram_totals.get(&(prev2, prev)) {
+                let count = *self.trigrams.get(&(prev2, prev, hanzi)).unwrap_or(&0);
+                if count > 0 {
+                    return count as f64 / total as f64;
+                }
+                backoff = BACKOFF_FACTOR;
+            }
+        }
+        match self.bigrams.get(&(prev, hanzi)) {
+            Some(&count) if count > 0 => backoff * count as f64 / self.bigram_totals[&prev] as f64,
+            _ => 0.0,
+        }
+    }
+}
+
+// Rescales the scores of matches by how likely each character is after context, relative to the likeliest one,
+// then sorts them again. Matches stay as they are if the model knows none of them in this context.
+pub(crate) fn rerank(matches: &mut [Match], model: &NgramModel, context: &str, weight: f32) {
+    let probabilities: Vec<f64> = matches.iter().map(|m| model.probability(context, m.hanzi)).collect();
+    let max_probability = probabilities.iter().cloned().fold(0.0, f64::max);
+    if max_probability <= 0.0 {
+        return;
+    }
+    for (m, probability) in matches.iter_mut().zip(probabilities) {
+        let factor = 1f32 - weight * (1f32 - (probability / max_probability) as f32);
+        // Like template weights, a lower factor pulls the score down whatever its sign
+        m.score -= (1f32 - factor) * m.score.abs();
+    }
+    // Stable, so equal scores keep the matcher's order
+    matches.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probability() {
+        let model = NgramModel::parse("# ngram\tcount\n自己\t30\n自由\t10\r\n\n而已\t5\n不得已\t4\n不得了\t1\n得到\t10\n").unwrap();
+        assert_eq!(model.probability("自", '己'), 0.75);
+        assert_eq!(model.probability("我们自", '由'), 0.25);
+        assert_eq!(model.probability("自", '已'), 0.0);
+        assert_eq!(model.probability("", '己'), 0.0);
+        assert_eq!(model.probability("不得", '已'), 0.8);
+        assert_eq!(model.probability("不得", '己'), 0.0);
+        // 不得到 isn't counted, so the bigram 得到 stands in for it, at a discount
+        assert_eq!(model.probability("得", '到'), 1.0);
+        assert_eq!(model.probability("不得", '到'), 0.4);
+        assert!(match NgramModel::parse("自己\t1\n自\t1\n") { Err(Error::InvalidNgramTable { line: 2 }) => true, _ => false });
+        assert!(match NgramModel::parse("自己 1\n") { Err(Error::InvalidNgramTable { line: 1 }) => true, _ => false });
+    }
+
+    #[test]
+    fn test_rerank() {
+        let model = NgramModel::parse("自己\t30\n自由\t10\n").unwrap();
+        let mut matches = vec![Match::new('已', 10.0), Match::new('己', 9.9), Match::new('巳', 9.5), Match::new('由', 5.0)];
+        rerank(&mut matches, &model, "自", 0.5);
+        let reranked: Vec<char> = matches.iter().map(|m| m.hanzi).collect();
+        assert_eq!(reranked, ['己', '已', '巳', '由']);
+        assert_eq!(matches[0].score, 9.9);
+        assert_eq!(matches[1].score, 5.0);
+        assert!((matches[3].score - 5.0 * (1.0 - 0.5 * (1.0 - 1.0 / 3.0))).abs() < 1e-5);
+        // Unknown context leaves the order alone
+        let mut matches = vec![Match::new('已', 10.0), Match::new('己', 9.9)];
+        rerank(&mut matches, &model, "而", 0.5);
+        assert!(matches[0].hanzi == '已' && matches[0].score == 10.0);
+    }
+}
@@ -0,0 +1,393 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::analyzed_character::*;
+use super::*;
+
+// Cleanup of raw pen input, run before analysis.
+// Lengths are given on the 256 x 256 reference canvas, and scaled to the canvas the strokes were drawn on.
+pub trait Preprocessor: Send + Sync {
+    fn process(&self, strokes: Vec<Stroke<PenPoint>>, canvas_size: f32) -> Vec<Stroke<PenPoint>>;
+}
+
+// Stages run one after the other
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Preprocessor>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    // Cleanup that suits most pen and touch input: duplicate points, hooks at the ends of strokes, and jitter
+    pub fn standard() -> Pipeline {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Deduplicate);
+        pipeline.push(RemoveHooks::default());
+        pipeline.push(Smooth::default());
+        pipeline
+    }
+
+    pub fn push<T: Preprocessor + 'static>(&mut self, stage: T) {
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    // Runs the stages on a copy of the strokes
+    pub fn run<P: InputPoint>(&self, strokes: &[Stroke<P>], canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        let strokes = strokes.iter().map(|stroke| Stroke {
+            points: stroke.points.iter().map(|point| PenPoint {
+                x: point.x(),
+                y: point.y(),
+                time: point.time(),
+                pressure: point.pressure(),
+            }).collect(),
+        }).collect();
+        self.process(strokes, canvas_size)
+    }
+}
+
+impl Preprocessor for Pipeline {
+    fn process(&self, strokes: Vec<Stroke<PenPoint>>, canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        self.stages.iter().fold(strokes, |strokes, stage| stage.process(strokes, canvas_size))
+    }
+}
+
+// Drops points that repeat the one before, as many devices report while the pen rests.
+// A tap, which is one point repeated, keeps its last point too, so it still has the two points of a stroke.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Deduplicate;
+
+impl Preprocessor for Deduplicate {
+    fn process(&self, mut strokes: Vec<Stroke<PenPoint>>, _canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        for stroke in strokes.iter_mut() {
+            let last = stroke.points.last().cloned();
+            stroke.points.dedup_by(|point, prev| point.x == prev.x && point.y == prev.y);
+            if stroke.points.len() == 1 {
+                stroke.points.extend(last);
+            }
+        }
+        strokes
+    }
+}
+
+// Replaces each stroke's points with points at even distances along it, so fast and slow parts are sampled alike
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct Resample {
+    pub spacing: f32,
+}
+
+impl Default for Resample {
+    fn default() -> Resample {
+        Resample {
+            spacing: 4.0,
+        }
+    }
+}
+
+impl Preprocessor for Resample {
+    fn process(&self, mut strokes: Vec<Stroke<PenPoint>>, canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        let spacing = self.spacing * canvas_size / REFERENCE_CANVAS_SIZE;
+        if spacing.is_nan() || spacing <= 0f32 {
+            return strokes;
+        }
+        for stroke in strokes.iter_mut() {
+            if stroke.points.len() < 2 {
+                continue;
+            }
+            let mut points: Vec<PenPoint> = vec![stroke.points[0]];
+            // Distance along the stroke still to go until the next point
+            let mut to_next = spacing;
+            for i in 1..stroke.points.len() {
+                let (from, to) = (stroke.points[i - 1], stroke.points[i]);
+                let length = dist(from, to);
+                let mut covered = 0f32;
+                while length - covered >= to_next {
+                    covered += to_next;
+                    points.push(interpolate(from, to, covered / length));
+                    to_next = spacing;
+                }
+                to_next -= length - covered;
+            }
+            // The stroke still ends where it ended; a tap keeps its two points
+            let last = *stroke.points.last().unwrap();
+            if points.len() < 2 || dist(*points.last().unwrap(), last) > 0f32 {
+                points.push(last);
+            }
+            stroke.points = points;
+        }
+        strokes
+    }
+}
+
+// Evens out jitter by averaging each point with its neighbors; the ends of strokes stay in place
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct Smooth {
+    pub passes: usize,
+}
+
+impl Default for Smooth {
+    fn default() -> Smooth {
+        Smooth {
+            passes: 1,
+        }
+    }
+}
+
+impl Preprocessor for Smooth {
+    fn process(&self, mut strokes: Vec<Stroke<PenPoint>>, _canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        for stroke in strokes.iter_mut() {
+            for _ in 0..self.passes {
+                let points = stroke.points.clone();
+                for i in 1..points.len().saturating_sub(1) {
+                    stroke.points[i].x = (points[i - 1].x + 2f32 * points[i].x + points[i + 1].x) / 4f32;
+                    stroke.points[i].y = (points[i - 1].y + 2f32 * points[i].y + points[i + 1].y) / 4f32;
+                }
+            }
+        }
+        strokes
+    }
+}
+
+// Cuts off hooks: short flicks at the start or end of a stroke, where the pen lands or lifts, that turn sharply
+// away from the rest of it. Only strokes at least three times longer than a hook can have one.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct RemoveHooks {
+    // Longest hook
+    pub max_length: f32,
+    // Least turn, in radians, between the hook and the rest of the stroke
+    pub min_angle: f32,
+}
+
+impl Default for RemoveHooks {
+    fn default() -> RemoveHooks {
+        RemoveHooks {
+            max_length: 10.0,
+            min_angle: std::f32::consts::FRAC_PI_2,
+        }
+    }
+}
+
+impl Preprocessor for RemoveHooks {
+    fn process(&self, mut strokes: Vec<Stroke<PenPoint>>, canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        let max_length = self.max_length * canvas_size / REFERENCE_CANVAS_SIZE;
+        for stroke in strokes.iter_mut() {
+            if get_length(&stroke.points) < 3f32 * max_length {
+                continue;
+            }
+            let start = get_hook_end(&stroke.points, max_length, self.min_angle);
+            stroke.points.drain(..start);
+            stroke.points.reverse();
+            let end = get_hook_end(&stroke.points, max_length, self.min_angle);
+            stroke.points.drain(..end);
+            stroke.points.reverse();
+        }
+        strokes
+    }
+}
+
+// Drops strokes shorter than min_length altogether, like specks from the pen touching the screen by accident.
+// Unlike the slips that analysis leaves out, these need no timestamps or pressure to tell; keep the length below a real dot's.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct DropTinyStrokes {
+    pub min_length: f32,
+}
+
+impl Default for DropTinyStrokes {
+    fn default() -> DropTinyStrokes {
+        DropTinyStrokes {
+            min_length: 3.0,
+        }
+    }
+}
+
+impl Preprocessor for DropTinyStrokes {
+    fn process(&self, mut strokes: Vec<Stroke<PenPoint>>, canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        let min_length = self.min_length * canvas_size / REFERENCE_CANVAS_SIZE;
+        strokes.retain(|stroke| get_length(&stroke.points) >= min_length);
+        strokes
+    }
+}
+
+// Joins a stroke to the one before if the pen was only up for a moment and came down where it had lifted,
+// as when it skips on the screen mid-stroke. Needs timestamps: strokes without them are never joined.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct JoinStrokes {
+    // Milliseconds
+    pub max_pause: f64,
+    pub max_distance: f32,
+}
+
+impl Default for JoinStrokes {
+    fn default() -> JoinStrokes {
+        JoinStrokes {
+            max_pause: 50.0,
+            max_distance: 10.0,
+        }
+    }
+}
+
+impl Preprocessor for JoinStrokes {
+    fn process(&self, strokes: Vec<Stroke<PenPoint>>, canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        let max_distance = self.max_distance * canvas_size / REFERENCE_CANVAS_SIZE;
+        let mut res: Vec<Stroke<PenPoint>> = Vec::with_capacity(strokes.len());
+        for stroke in strokes {
+            let join = match (res.last().and_then(|prev| prev.points.last()), stroke.points.first()) {
+                (Some(lifted), Some(landed)) => match (lifted.time, landed.time) {
+                    (Some(lift_time), Some(land_time)) => land_time - lift_time <= self.max_pause && dist(*lifted, *landed) <= max_distance,
+                    _ => false,
+                },
+                _ => false,
+            };
+            if join {
+                res.last_mut().unwrap().points.extend(stroke.points);
+            }
+            else {
+                res.push(stroke);
+            }
+        }
+        res
+    }
+}
+
+fn dist(a: PenPoint, b: PenPoint) -> f32 {
+    ((a.x - b.x) * (a.x - b.x) + (a.y - b.y) * (a.y - b.y)).sqrt()
+}
+
+fn get_length(points: &[PenPoint]) -> f32 {
+    points.windows(2).map(|pair| dist(pair[0], pair[1])).sum()
+}
+
+// The point share of the way from a to b; time and pressure too, where both points have them
+fn interpolate(a: PenPoint, b: PenPoint, share: f32) -> PenPoint {
+    PenPoint {
+        x: a.x + (b.x - a.x) * share,
+        y: a.y + (b.y - a.y) * share,
+        time: match (a.time, b.time) {
+            (Some(a_time), Some(b_time)) => Some(a_time + (b_time - a_time) * share as f64),
+            _ => None,
+        },
+        pressure: match (a.pressure, b.pressure) {
+            (Some(a_pressure), Some(b_pressure)) => Some(a_pressure + (b_pressure - a_pressure) * share),
+            _ => None,
+        },
+    }
+}
+
+// Index of the corner where a hook at the start of the points ends; 0 if they start without a hook.
+// A corner within max_length of the start is a hook's end if the way there and the next max_length of the stroke
+// point in directions at least min_angle apart; of several, the sharpest turn wins.
+fn get_hook_end(points: &[PenPoint], max_length: f32, min_angle: f32) -> usize {
+    let mut res = 0;
+    let mut sharpest = min_angle;
+    let mut length = 0f32;
+    for k in 1..points.len().saturating_sub(1) {
+        length += dist(points[k - 1], points[k]);
+        if length > max_length {
+            break;
+        }
+        // Where the stroke is max_length past the corner, or its end
+        let mut ahead = k + 1;
+        let mut ahead_length = dist(points[k], points[ahead]);
+        while ahead + 1 < points.len() && ahead_length < max_length {
+            ahead_length += dist(points[ahead], points[ahead + 1]);
+            ahead += 1;
+        }
+        let before = (points[k].y - points[0].y).atan2(points[k].x - points[0].x);
+        let after = (points[ahead].y - points[k].y).atan2(points[ahead].x - points[k].x);
+        let mut turn = (after - before).abs();
+        if turn > std::f32::consts::PI {
+            turn = 2f32 * std::f32::consts::PI - turn;
+        }
+        if turn >= sharpest {
+            sharpest = turn;
+            res = k;
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_stroke(points: &[(f32, f32)]) -> Stroke<PenPoint> {
+        Stroke {
+            points: points.iter().map(|&(x, y)| PenPoint { x: x, y: y, time: None, pressure: None }).collect(),
+        }
+    }
+
+    fn to_coords(stroke: &Stroke<PenPoint>) -> Vec<(f32, f32)> {
+        stroke.points.iter().map(|point| (point.x, point.y)).collect()
+    }
+
+    #[test]
+    fn test_stages() {
+        let res = Deduplicate.process(vec![to_stroke(&[(1.0, 2.0), (1.0, 2.0), (3.0, 2.0), (1.0, 2.0)]), to_stroke(&[(5.0, 5.0), (5.0, 5.0), (5.0, 5.0)])], 256.0);
+        assert_eq!(to_coords(&res[0]), [(1.0, 2.0), (3.0, 2.0), (1.0, 2.0)]);
+        assert_eq!(to_coords(&res[1]), [(5.0, 5.0), (5.0, 5.0)]);
+        // On a canvas twice the reference size, spacing doubles
+        let res = Resample { spacing: 4.0 }.process(vec![to_stroke(&[(0.0, 0.0), (10.0, 0.0), (10.0, 9.0)])], 512.0);
+        assert_eq!(to_coords(&res[0]), [(0.0, 0.0), (8.0, 0.0), (10.0, 6.0), (10.0, 9.0)]);
+        let res = Smooth { passes: 1 }.process(vec![to_stroke(&[(0.0, 0.0), (4.0, 4.0), (8.0, 0.0)])], 256.0);
+        assert_eq!(to_coords(&res[0]), [(0.0, 0.0), (4.0, 2.0), (8.0, 0.0)]);
+        let res = DropTinyStrokes { min_length: 3.0 }.process(vec![to_stroke(&[(0.0, 0.0), (2.0, 0.0)]), to_stroke(&[(0.0, 0.0), (3.0, 0.0)])], 256.0);
+        assert_eq!(res.len(), 1);
+        // A horizontal stroke that starts with a flick up into it, and ends with a hook down and back
+        let hooked = to_stroke(&[(18.0, 22.0), (15.0, 15.0), (40.0, 15.0), (60.0, 15.0), (80.0, 15.0), (78.0, 22.0)]);
+        let res = RemoveHooks::default().process(vec![hooked], 256.0);
+        assert_eq!(to_coords(&res[0]), [(15.0, 15.0), (40.0, 15.0), (60.0, 15.0), (80.0, 15.0)]);
+        // A plain corner further in stays
+        let corner = to_stroke(&[(10.0, 15.0), (40.0, 15.0), (60.0, 15.0), (60.0, 50.0)]);
+        let res = RemoveHooks::default().process(vec![corner], 256.0);
+        assert_eq!(res[0].points.len(), 4);
+    }
+
+    #[test]
+    fn test_join_strokes() {
+        let timed = |points: &[(f32, f32, f64)]| Stroke {
+            points: points.iter().map(|&(x, y, time)| PenPoint { x: x, y: y, time: Some(time), pressure: None }).collect(),
+        };
+        let strokes = vec![
+            timed(&[(0.0, 0.0, 0.0), (30.0, 0.0, 50.0)]),
+            // Skipped: down again 20 ms later, 5 units on
+            timed(&[(35.0, 0.0, 70.0), (60.0, 0.0, 100.0)]),
+            // A new stroke: long pause
+            timed(&[(62.0, 0.0, 400.0), (62.0, 30.0, 450.0)]),
+        ];
+        let res = JoinStrokes::default().process(strokes, 256.0);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].points.len(), 4);
+        // Without timestamps, strokes are left alone
+        let res = JoinStrokes::default().process(vec![to_stroke(&[(0.0, 0.0), (30.0, 0.0)]), to_stroke(&[(30.0, 0.0), (60.0, 0.0)])], 256.0);
+        assert_eq!(res.len(), 2);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Deduplicate);
+        pipeline.push(DropTinyStrokes::default());
+        let strokes = vec![
+            Stroke { points: vec![Point { x: 1, y: 1 }, Point { x: 1, y: 1 }, Point { x: 9, y: 1 }] },
+            Stroke { points: vec![Point { x: 5, y: 5 }, Point { x: 5, y: 5 }, Point { x: 5, y: 5 }] },
+        ];
+        let res = pipeline.run(&strokes, 256.0);
+        assert_eq!(res.len(), 1);
+        assert_eq!(to_coords(&res[0]), [(1.0, 1.0), (9.0, 1.0)]);
+        assert_eq!(Pipeline::standard().len(), 3);
+    }
+}
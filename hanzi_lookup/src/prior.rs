@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use super::error::*;
+
+// How common each character is, from a frequency table. Lookup uses it to favor common characters
+// when the drawing fits several about equally well.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrequencyPrior {
+    // Log of each character's count, scaled so the most frequent character has 1.0
+    priors: HashMap<char, f32>,
+}
+
+impl FrequencyPrior {
+    pub fn from_counts<I: IntoIterator<Item = (char, u64)>>(counts: I) -> FrequencyPrior {
+        let counts: Vec<(char, u64)> = counts.into_iter().collect();
+        let max_count = counts.iter().map(|&(_, count)| count).max().unwrap_or(0);
+        let log_max = (max_count as f64 + 1.0).ln();
+        let mut priors = HashMap::with_capacity(counts.len());
+        for (hanzi, count) in counts {
+            // Zero counts from a table where nothing was counted still come out as 0.0, not NaN
+            let prior = if log_max > 0.0 { (count as f64 + 1.0).ln() / log_max } else { 0.0 };
+            priors.insert(hanzi, prior as f32);
+        }
+        FrequencyPrior {
+            priors,
+        }
+    }
+
+    // Parses a table with one character and its count per line, separated by a tab.
+    // Empty lines and lines starting with # are skipped; a character listed twice has its counts added up.
+    pub fn parse(text: &str) -> Result<FrequencyPrior, Error> {
+        let mut counts: HashMap<char, u64> = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let mut hanzi_chars = fields.next().unwrap_or("").chars();
+            let hanzi = match (hanzi_chars.next(), hanzi_chars.next()) {
+                (Some(hanzi), None) => hanzi,
+                _ => return Err(Error::InvalidFrequencyTable { line: i + 1 }),
+            };
+            let count: u64 = match (fields.next().map(|field| field.trim().parse()), fields.next()) {
+                (Some(Ok(count)), None) => count,
+                _ => return Err(Error::InvalidFrequencyTable { line: i + 1 }),
+            };
+            *counts.entry(hanzi).or_insert(0) += count;
+        }
+        Ok(FrequencyPrior::from_counts(counts))
+    }
+
+    // Between 0.0 for characters missing from the table and 1.0 for the most frequent one
+    pub fn prior(&self, hanzi: char) -> f32 {
+        *self.priors.get(&hanzi).unwrap_or(&0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let prior = FrequencyPrior::parse("# char\tcount\n的\t9999\n\n气\t99\r\n氕\t0\n气\t0\n").unwrap();
+        assert_eq!(prior.prior('的'), 1.0);
+        assert!((prior.prior('气') - 0.5).abs() < 1e-6);
+        assert_eq!(prior.prior('氕'), 0.0);
+        assert_eq!(prior.prior('氣'), 0.0);
+        assert!(match FrequencyPrior::parse("的\t1\n气 99\n") { Err(Error::InvalidFrequencyTable { line: 2 }) => true, _ => false });
+        assert!(match FrequencyPrior::parse("的的\t1\n") { Err(Error::InvalidFrequencyTable { line: 1 }) => true, _ => false });
+        assert!(match FrequencyPrior::parse("的\t-1\n") { Err(Error::InvalidFrequencyTable { line: 1 }) => true, _ => false });
+        assert!(match FrequencyPrior::parse("的\t1\t2\n") { Err(Error::InvalidFrequencyTable { line: 1 }) => true, _ => false });
+        assert_eq!(FrequencyPrior::parse("").unwrap().prior('的'), 0.0);
+    }
+}
@@ -0,0 +1,1026 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::analyzed_character::*;
+use super::components::*;
+use super::dataset::*;
+use super::error::*;
+use super::explanation::*;
+use super::grading::*;
+use super::match_collector::*;
+use super::matcher::*;
+use super::ngram::*;
+use super::preprocessing::*;
+use super::prior::*;
+use super::segmentation::*;
+use super::segmenter::*;
+use super::session::*;
+use super::tracing::*;
+use super::write_over::*;
+use super::*;
+
+// Candidates gathered for reranking by context, if the caller asks for fewer
+const RERANK_POOL_SIZE: usize = 32;
+
+// A self-contained handwriting recognizer: owns its character dataset and matching parameters.
+// Lookups take &self and allocate their own scratch space, so one instance can be shared by many threads.
+pub struct Recognizer {
+    matcher: Matcher,
+    dataset: Dataset,
+    prior: Option<FrequencyPrior>,
+    language_model: Option<NgramModel>,
+    preprocessing: Option<Pipeline>,
+    segmenter: Box<dyn Segmenter>,
+    // Each template's self-match score, in dataset order; what confidence is measured against
+    self_scores: Vec<f32>,
+    // Each template's component windows, in dataset order, by the input stroke count they were made for; filled in on first use
+    component_windows: Mutex<HashMap<usize, Arc<Vec<Vec<CharData>>>>>,
+}
+
+impl Recognizer {
+    // Recognizer over the embedded dataset with default parameters
+    #[cfg(feature = "embedded-data")]
+    pub fn new() -> Recognizer {
+        let params = MatcherParams::default();
+        Recognizer::with_params(&params)
+    }
+
+    #[cfg(feature = "embedded-data")]
+    pub fn with_params(params: &MatcherParams) -> Recognizer {
+        Recognizer::with_dataset(Dataset::embedded(), params)
+    }
+
+    pub fn with_dataset(dataset: Dataset, params: &MatcherParams) -> Recognizer {
+        let matcher = Matcher::with_params(params);
+        let self_scores = dataset.chars.iter().map(|char_data| matcher.self_match_score(char_data)).collect();
+        Recognizer {
+            matcher,
+            dataset,
+            prior: None,
+            language_model: None,
+            preprocessing: None,
+            segmenter: Box::new(CornerSegmenter::default()),
+            self_scores,
+            component_windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Loads a dataset from a byte slice, a reader or a file; uses default parameters
+    pub fn from_bytes(bytes: &[u8]) -> Result<Recognizer, DatasetError> {
+        Ok(Recognizer::with_dataset(Dataset::from_bytes(bytes)?, &MatcherParams::default()))
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Recognizer, DatasetError> {
+        Ok(Recognizer::with_dataset(Dataset::from_reader(reader)?, &MatcherParams::default()))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Recognizer, DatasetError> {
+        Ok(Recognizer::with_dataset(Dataset::from_file(path)?, &MatcherParams::default()))
+    }
+
+    pub fn dataset(&self) -> &Dataset {
+        &self.dataset
+    }
+
+    pub fn params(&self) -> &MatcherParams {
+        self.matcher.params()
+    }
+
+    // Frequency table that lookups weigh in as much as LookupOptions::prior_weight asks; None for purely geometric scores
+    pub fn set_prior(&mut self, prior: Option<FrequencyPrior>) {
+        self.prior = prior;
+    }
+
+    pub fn prior(&self) -> Option<&FrequencyPrior> {
+        self.prior.as_ref()
+    }
+
+    // Bigram and trigram counts for reranking by LookupOptions::context; None to ignore the context
+    pub fn set_language_model(&mut self, language_model: Option<NgramModel>) {
+        self.language_model = language_model;
+    }
+
+    pub fn language_model(&self) -> Option<&NgramModel> {
+        self.language_model.as_ref()
+    }
+
+    // Cleanup run on the strokes before every lookup, explanation and grade, and in sessions; None to take strokes as drawn.
+    // Grades and explanations refer to the strokes as the pipeline leaves them. Tracing takes each stroke as drawn.
+    pub fn set_preprocessing(&mut self, preprocessing: Option<Pipeline>) {
+        self.preprocessing = preprocessing;
+    }
+
+    pub fn preprocessing(&self) -> Option<&Pipeline> {
+        self.preprocessing.as_ref()
+    }
+
+    // How input strokes are split into substrokes; CornerSegmenter unless set.
+    // The dataset's templates stay as they are, so a different segmenter may need a looser lookup to match them.
+    pub fn set_segmenter<S: Segmenter + 'static>(&mut self, segmenter: S) {
+        self.segmenter = Box::new(segmenter);
+    }
+
+    pub fn segmenter(&self) -> &dyn Segmenter {
+        self.segmenter.as_ref()
+    }
+
+    // Returns up to limit best matches for the strokes, highest score first
+    pub fn lookup<P: InputPoint>(&self, strokes: &[Stroke<P>], limit: usize) -> Vec<Match> {
+        let options = LookupOptions::with_looseness(self.params().DEFAULT_LOOSENESS);
+        self.lookup_with(strokes, limit, &options)
+    }
+
+    // Lookup with per-call looseness and optional automatic widening
+    pub fn lookup_with<P: InputPoint>(&self, strokes: &[Stroke<P>], limit: usize, options: &LookupOptions) -> Vec<Match> {
+        // Analyze once; widening only changes which candidates are compared
+        let strokes = self.preprocess(strokes, options.canvas_size);
+        let input_char = AnalyzedCharacter::from_strokes_with(&strokes, options.canvas_size, self.segmenter());
+        let weights = self.template_weights(options);
+        self.lookup_ranked(limit, options, |pool_size| {
+            lookup_widening(options, input_char.sub_stroke_count, |looseness| self.lookup_analyzed(&input_char, pool_size, self.lookup_context(looseness, options, weights.as_ref(), None)))
+        })
+    }
+
+    // Top matches as lookup_with finds them, each with the alignment behind its score.
+    // With an order-free weight, the alignment only accounts for the ordered share of the score.
+    pub fn explain<P: InputPoint>(&self, strokes: &[Stroke<P>], limit: usize, options: &LookupOptions) -> Vec<Explanation> {
+        let strokes = self.preprocess(strokes, options.canvas_size);
+        let input_char = AnalyzedCharacter::from_strokes_with(&strokes, options.canvas_size, self.segmenter());
+        let weights = self.template_weights(options);
+        // Alignments are traced at the looseness the matches were found at
+        let mut looseness = options.looseness;
+        let matches = lookup_widening(options, input_char.sub_stroke_count, |next_looseness| {
+            looseness = next_looseness;
+            self.lookup_analyzed(&input_char, limit, self.lookup_context(next_looseness, options, weights.as_ref(), None))
+        });
+        matches.iter()
+            .filter_map(|m| self.dataset.chars.iter().find(|char_data| char_data.hanzi == m.hanzi && char_data.tag == m.tag && char_data.region == m.region))
+            .map(|char_data| self.matcher.explain(&input_char, looseness, options.prefix, char_data))
+            .collect()
+    }
+
+    // Alignment against one character, such as the one the writer meant; None if the dataset doesn't have it.
+    // The character is explained even if lookup would not consider it at this looseness, against its best matching template.
+    pub fn explain_char<P: InputPoint>(&self, strokes: &[Stroke<P>], hanzi: char, options: &LookupOptions) -> Option<Explanation> {
+        let strokes = self.preprocess(strokes, options.canvas_size);
+        let input_char = AnalyzedCharacter::from_strokes_with(&strokes, options.canvas_size, self.segmenter());
+        let mut best: Option<Explanation> = None;
+        for char_data in self.dataset.templates(hanzi) {
+            let explanation = self.matcher.explain(&input_char, options.looseness, options.prefix, char_data);
+            if best.as_ref().map_or(true, |best| explanation.score > best.score) {
+                best = Some(explanation);
+            }
+        }
+        best
+    }
+
+    // Characters that contain the strokes as a component, such as 氵 or 钅, anywhere and at any size.
+    // The strokes may be interrupted by one stroke of the character's that isn't part of the component.
+    pub fn lookup_component<P: InputPoint>(&self, strokes: &[Stroke<P>], limit: usize, options: &LookupOptions) -> Vec<Match> {
+        let strokes = self.preprocess(strokes, options.canvas_size);
+        let input_char = AnalyzedCharacter::from_strokes_with(&strokes, options.canvas_size, self.segmenter());
+        let input_sub_strokes = input_char.get_analyzed_strokes();
+        let weights = self.template_weights(options);
+        let mut res: Vec<Match> = Vec::with_capacity(limit);
+        if input_sub_strokes.is_empty() {
+            return res;
+        }
+        let component_windows = self.component_windows(input_char.analyzed_strokes.len());
+        {
+            let mut collector = MatchCollector::new(&mut res, limit);
+            for (cix, char_data) in self.dataset.chars.iter().enumerate() {
+                let weight = match get_weight(weights.as_ref(), cix) {
+                    Some(weight) => weight,
+                    None => continue,
+                };
+                let windows = &component_windows[cix];
+                if windows.is_empty() {
+                    continue;
+                }
+                let score = windows.iter().map(|window| self.matcher.match_component(&input_sub_strokes, window)).fold(f32::MIN, f32::max);
+                // A perfect component scores about 1 for each of its substrokes
+                let sub_stroke_count = input_sub_strokes.len();
+                collector.file_match(Match {
+                    hanzi: char_data.hanzi,
+                    score: score - (1f32 - weight) * score.abs(),
+                    tag: char_data.tag.clone(),
+                    region: char_data.region.clone(),
+                    confidence: get_confidence(score, sub_stroke_count as f32, sub_stroke_count, sub_stroke_count),
+                });
+            }
+        }
+        res.retain(|m| m.confidence >= options.min_confidence);
+        res
+    }
+
+    // Reads a line of characters written one after another in direction, without clearing the canvas in between.
+    // Returns the split of the strokes into characters that matches best, with up to limit candidates for each.
+    pub fn lookup_line<P: InputPoint>(&self, strokes: &[Stroke<P>], direction: LineDirection, limit: usize, options: &LookupOptions) -> LineMatch {
+        let spans = get_spans(strokes, direction);
+        let height = get_line_height(strokes, direction);
+        // Each character is about as big as the line is high
+        let mut first_options = options.clone();
+        first_options.canvas_size = height;
+        // The preceding text is only known for the line's first character
+        let mut other_options = first_options.clone();
+        other_options.context = None;
+        segment_line(&spans, height, self.params().MAX_CHARACTER_STROKE_COUNT, |first, end| {
+            let segment: Vec<Stroke<P>> = strokes[first..end].iter().map(|stroke| Stroke { points: stroke.points.clone() }).collect();
+            self.lookup_with(&segment, limit, if first == 0 { &first_options } else { &other_options })
+        })
+    }
+
+    pub fn try_lookup_line<P: InputPoint>(&self, strokes: &[Stroke<P>], direction: LineDirection, limit: usize, options: &LookupOptions) -> Result<LineMatch, Error> {
+        if limit == 0 {
+            return Err(Error::ZeroLimit);
+        }
+        check_options(options)?;
+        check_strokes(strokes)?;
+        Ok(self.lookup_line(strokes, direction, limit, options))
+    }
+
+    // How well the strokes reproduce a given character, stroke by stroke; None if the dataset doesn't have it.
+    // A character with several templates is graded against the one the strokes reproduce best.
+    pub fn grade<P: InputPoint>(&self, strokes: &[Stroke<P>], target: char, options: &LookupOptions) -> Option<Grade> {
+        let strokes = self.preprocess(strokes, options.canvas_size);
+        let input_char = AnalyzedCharacter::from_strokes_with(&strokes, options.canvas_size, self.segmenter());
+        let mut best: Option<Grade> = None;
+        for char_data in self.dataset.templates(target) {
+            let grade = grade_char(&self.matcher, &input_char, char_data);
+            if best.as_ref().map_or(true, |best| grade.score > best.score) {
+                best = Some(grade);
+            }
+        }
+        best
+    }
+
+    // Starts an incremental session that keeps work between strokes
+    pub fn session<P: InputPoint>(&self) -> Session<'_, P> {
+        Session::new(self)
+    }
+
+    // Starts continuous input of characters written on top of each other, with default settings
+    pub fn write_over<P: InputPoint>(&self) -> WriteOver<'_, P> {
+        WriteOver::new(self)
+    }
+
+    // Starts guided writing of target, stroke by stroke; None if the dataset doesn't have it
+    pub fn tracer<P: InputPoint>(&self, target: char) -> Option<Tracer<'_, P>> {
+        Tracer::new(self, target)
+    }
+
+    // Copy of the strokes as the preprocessing pipeline leaves them; unchanged if there is none
+    pub(crate) fn preprocess<P: InputPoint>(&self, strokes: &[Stroke<P>], canvas_size: f32) -> Vec<Stroke<PenPoint>> {
+        match self.preprocessing {
+            Some(ref pipeline) => pipeline.run(strokes, canvas_size),
+            None => Pipeline::new().run(strokes, canvas_size),
+        }
+    }
+
+    // Every template's component windows for input of stroke_count strokes, in dataset order.
+    // Made once for each stroke count and kept, since they only depend on the dataset.
+    fn component_windows(&self, stroke_count: usize) -> Arc<Vec<Vec<CharData>>> {
+        let mut component_windows = self.component_windows.lock().unwrap_or_else(|err| err.into_inner());
+        component_windows.entry(stroke_count)
+            .or_insert_with(|| Arc::new(self.dataset.chars.iter().map(|char_data| get_component_windows(char_data, stroke_count)).collect()))
+            .clone()
+    }
+
+    pub(crate) fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    pub(crate) fn template_weights(&self, options: &LookupOptions) -> Option<TemplateWeights> {
+        get_template_weights(&self.dataset.chars, self.prior.as_ref(), options)
+    }
+
+    // Calls lookup for the number of matches to gather; if the options have a context to rerank by, that's a larger pool.
+    // Matches below the options' minimum confidence are dropped at the end.
+    pub(crate) fn lookup_ranked<F>(&self, limit: usize, options: &LookupOptions, lookup: F) -> Vec<Match>
+        where F: FnOnce(usize) -> Vec<Match> {
+        let mut matches = match (self.language_model.as_ref(), options.context.as_ref()) {
+            (Some(model), Some(context)) if options.context_weight > 0.0 => {
+                let mut matches = lookup(usize::max(limit, RERANK_POOL_SIZE));
+                rerank(&mut matches, model, context, options.context_weight);
+                matches.truncate(limit);
+                matches
+            }
+            _ => lookup(limit),
+        };
+        matches.retain(|m| m.confidence >= options.min_confidence);
+        matches
+    }
+
+    // Lookup context at this looseness for these options against this recognizer's templates
+    pub(crate) fn lookup_context<'a>(&'a self, looseness: f32, options: &LookupOptions, weights: Option<&'a TemplateWeights>, cache: Option<&'a mut ScoreCache>) -> LookupContext<'a> {
+        LookupContext {
+            looseness,
+            mode: MatchMode::from_options(options),
+            weights,
+            self_scores: Some(&self.self_scores),
+            cache,
+        }
+    }
+
+    pub(crate) fn lookup_analyzed<P: InputPoint>(&self, input_char: &AnalyzedCharacter<P>, limit: usize, context: LookupContext) -> Vec<Match> {
+        let mut res: Vec<Match> = Vec::with_capacity(limit);
+        {
+            let mut collector = MatchCollector::new(&mut res, limit);
+            self.matcher.lookup_analyzed(&self.dataset.chars, input_char, context, &mut collector);
+        }
+        res
+    }
+
+    // Like lookup, but rejects malformed input and a zero limit with an error instead of panicking
+    pub fn try_lookup<P: InputPoint>(&self, strokes: &[Stroke<P>], limit: usize) -> Result<Vec<Match>, Error> {
+        let options = LookupOptions::with_looseness(self.params().DEFAULT_LOOSENESS);
+        self.try_lookup_with(strokes, limit, &options)
+    }
+
+    pub fn try_lookup_with<P: InputPoint>(&self, strokes: &[Stroke<P>], limit: usize, options: &LookupOptions) -> Result<Vec<Match>, Error> {
+        if limit == 0 {
+            return Err(Error::ZeroLimit);
+        }
+        check_options(options)?;
+        check_strokes(strokes)?;
+        Ok(self.lookup_with(strokes, limit, options))
+    }
+}
+
+// Calls lookup_at with the options' looseness, then keeps widening while auto_widen asks for it
+pub(crate) fn lookup_widening<F>(options: &LookupOptions, sub_stroke_count: usize, mut lookup_at: F) -> Vec<Match>
+    where F: FnMut(f32) -> Vec<Match> {
+    let mut looseness = options.looseness;
+    loop {
+        let res = lookup_at(looseness);
+        let widen = match options.auto_widen {
+            Some(widen) => widen,
+            None => return res,
+        };
+        let min_score = widen.min_score * sub_stroke_count as f32;
+        let next_looseness = f32::min(looseness + widen.step, f32::min(widen.max_looseness, 1.0));
+        let good_enough = match res.first() {
+            Some(best) => best.score >= min_score,
+            None => false,
+        };
+        if good_enough || next_looseness <= looseness {
+            return res;
+        }
+        looseness = next_looseness;
+    }
+}
+
+pub(crate) fn check_options(options: &LookupOptions) -> Result<(), Error> {
+    if !(options.looseness >= 0.0 && options.looseness <= 1.0) {
+        return Err(Error::InvalidOptions(format!("Looseness must be between 0 and 1, got {}.", options.looseness)));
+    }
+    if !(options.canvas_size > 0.0 && options.canvas_size.is_finite()) {
+        return Err(Error::InvalidOptions(format!("Canvas size must be positive, got {}.", options.canvas_size)));
+    }
+    if let Some(prior_weight) = options.prior_weight {
+        if !(0.0..=1.0).contains(&prior_weight) {
+            return Err(Error::InvalidOptions(format!("Prior weight must be between 0 and 1, got {}.", prior_weight)));
+        }
+    }
+    if !(options.context_weight >= 0.0 && options.context_weight <= 1.0) {
+        return Err(Error::InvalidOptions(format!("Context weight must be between 0 and 1, got {}.", options.context_weight)));
+    }
+    if !(options.order_free_weight >= 0.0 && options.order_free_weight <= 1.0) {
+        return Err(Error::InvalidOptions(format!("Order-free weight must be between 0 and 1, got {}.", options.order_free_weight)));
+    }
+    if !(options.min_confidence >= 0.0 && options.min_confidence <= 1.0) {
+        return Err(Error::InvalidOptions(format!("Minimum confidence must be between 0 and 1, got {}.", options.min_confidence)));
+    }
+    if let Some(ref boosts) = options.boosts {
+        if let Some((hanzi, boost)) = boosts.iter().find(|&(_, boost)| !(*boost >= 0.0 && boost.is_finite())) {
+            return Err(Error::InvalidOptions(format!("Boost for {} must be a non-negative number, got {}.", hanzi, boost)));
+        }
+    }
+    if let Some(widen) = options.auto_widen {
+        if widen.step.is_nan() || widen.step <= 0.0 {
+            return Err(Error::InvalidOptions(format!("Widening step must be positive, got {}.", widen.step)));
+        }
+        if !(widen.max_looseness >= 0.0 && widen.max_looseness <= 1.0) {
+            return Err(Error::InvalidOptions(format!("Maximum looseness must be between 0 and 1, got {}.", widen.max_looseness)));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn check_strokes<P: InputPoint>(strokes: &[Stroke<P>]) -> Result<(), Error> {
+    if strokes.is_empty() {
+        return Err(Error::NoStrokes);
+    }
+    for (i, stroke) in strokes.iter().enumerate() {
+        if stroke.points.len() < 2 {
+            return Err(Error::TooFewPoints { stroke: i });
+        }
+        for (j, point) in stroke.points.iter().enumerate() {
+            if !point.x().is_finite() || !point.y().is_finite() {
+                return Err(Error::InvalidCoordinate { stroke: i, point: j });
+            }
+            let time_ok = point.time().map_or(true, |time| time.is_finite());
+            let pressure_ok = point.pressure().map_or(true, |pressure| pressure.is_finite());
+            if !time_ok || !pressure_ok {
+                return Err(Error::InvalidPenData { stroke: i, point: j });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "embedded-data")]
+impl Default for Recognizer {
+    fn default() -> Recognizer {
+        Recognizer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+    use super::super::test_samples::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<Recognizer>();
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-data")]
+    fn test_shared_lookup() {
+        let recognizer = Arc::new(Recognizer::new());
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let recognizer = recognizer.clone();
+            handles.push(thread::spawn(move || {
+                let sample = parse_sample(STROKES_2);
+                recognizer.lookup(&sample, 8)
+            }));
+        }
+        for handle in handles {
+            let res = handle.join().unwrap();
+            assert_eq!(res.len(), 8);
+            assert!(res[0].hanzi == '十');
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-data")]
+    fn test_custom_params() {
+        let mut params = MatcherParams::default();
+        params.CORRECT_NUM_STROKES_BONUS = 0.0;
+        let recognizer = Recognizer::with_params(&params);
+        assert_eq!(*recognizer.params(), params);
+        let res = recognizer.lookup(&parse_sample(STROKES_2), 4);
+        assert!(res[0].hanzi == '十');
+    }
+
+    #[test]
+    fn test_try_lookup() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let res = recognizer.try_lookup(&parse_sample(STROKES_2), 4).unwrap();
+        assert!(res[0].hanzi == '十');
+        assert!(match recognizer.try_lookup(&parse_sample(STROKES_2), 0) { Err(Error::ZeroLimit) => true, _ => false });
+        let no_strokes: Vec<Stroke> = Vec::new();
+        assert!(match recognizer.try_lookup(&no_strokes, 4) { Err(Error::NoStrokes) => true, _ => false });
+        let dot = parse_sample("[[[10,10],[200,200]],[[50,50]]]");
+        assert!(match recognizer.try_lookup(&dot, 4) { Err(Error::TooFewPoints { stroke: 1 }) => true, _ => false });
+    }
+
+    #[test]
+    fn test_looseness() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        // 氣 with its two middle dots missing: the default window is too narrow to consider it
+        let mut strokes = parse_sample(STROKES_4);
+        strokes.remove(4);
+        strokes.remove(4);
+        let res = recognizer.lookup_with(&strokes, 8, &LookupOptions::with_looseness(0.15));
+        assert!(res.iter().all(|m| m.hanzi != '氣'));
+        let res = recognizer.lookup_with(&strokes, 8, &LookupOptions::with_looseness(0.3));
+        assert!(res[0].hanzi == '氣');
+        let mut options = LookupOptions::with_looseness(0.15);
+        options.auto_widen = Some(AutoWiden::default());
+        let res = recognizer.lookup_with(&strokes, 8, &options);
+        assert!(res[0].hanzi == '氣');
+        // Complete input is found at any looseness
+        let res = recognizer.lookup_with(&parse_sample(STROKES_2), 8, &LookupOptions::with_looseness(0.0));
+        assert!(res[0].hanzi == '十');
+        let res = recognizer.lookup_with(&parse_sample(STROKES_2), 8, &LookupOptions::with_looseness(1.0));
+        assert!(res[0].hanzi == '十');
+    }
+
+    #[test]
+    fn test_float_input() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        // Same 十, drawn on a 2048 x 2048 canvas
+        let strokes = parse_sample(STROKES_2);
+        let mut scaled: Vec<Stroke<PointF>> = Vec::new();
+        for stroke in &strokes {
+            scaled.push(Stroke {
+                points: stroke.points.iter().map(|pt| PointF { x: pt.x as f32 * 8.0, y: pt.y as f32 * 8.0 }).collect(),
+            });
+        }
+        let mut options = LookupOptions::default();
+        options.canvas_size = 2048.0;
+        assert_eq!(recognizer.lookup_with(&scaled, 8, &options), recognizer.lookup(&strokes, 8));
+        scaled[0].points[1].x = std::f32::INFINITY;
+        assert!(match recognizer.try_lookup_with(&scaled, 8, &options) { Err(Error::InvalidCoordinate { stroke: 0, point: 1 }) => true, _ => false });
+        options.canvas_size = 0.0;
+        assert!(match recognizer.try_lookup_with(&strokes, 8, &options) { Err(Error::InvalidOptions(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let strokes = parse_sample(STROKES_2);
+        let options = LookupOptions::with_looseness(1.5);
+        assert!(match recognizer.try_lookup_with(&strokes, 8, &options) { Err(Error::InvalidOptions(_)) => true, _ => false });
+        let mut widen = AutoWiden::default();
+        widen.step = 0.0;
+        let mut options = LookupOptions::with_looseness(0.15);
+        options.auto_widen = Some(widen);
+        assert!(match recognizer.try_lookup_with(&strokes, 8, &options) { Err(Error::InvalidOptions(_)) => true, _ => false });
+        assert!(recognizer.try_lookup_with(&strokes, 8, &LookupOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_prior() {
+        let mut recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let strokes = parse_sample(STROKES_2);
+        let res = recognizer.lookup(&strokes, 8);
+        assert!(res[0].hanzi == '十');
+        // With the runner-up far more frequent, and the prior counting fully, the runner-up wins
+        let runner_up = res[1].hanzi;
+        recognizer.set_prior(Some(FrequencyPrior::from_counts(vec![(runner_up, 1000000), ('十', 1)])));
+        // Without a weight, the prior is left out
+        let mut options = LookupOptions::default();
+        assert_eq!(recognizer.lookup_with(&strokes, 8, &options), res);
+        options.prior_weight = Some(1.0);
+        assert!(recognizer.lookup_with(&strokes, 8, &options)[0].hanzi == runner_up);
+        options.prior_weight = Some(0.0);
+        assert_eq!(recognizer.lookup_with(&strokes, 8, &options), res);
+        // Boosts work with or without a prior
+        let boosted = res[2].hanzi;
+        let mut boosts = HashMap::new();
+        boosts.insert(boosted, 2.0);
+        options.boosts = Some(boosts);
+        let boosted_res = recognizer.lookup_with(&strokes, 8, &options);
+        assert!(boosted_res[0].hanzi == boosted);
+        assert!((boosted_res[0].score - res[2].score * 2.0).abs() < 1e-4);
+        options.prior_weight = Some(1.5);
+        assert!(match recognizer.try_lookup_with(&strokes, 8, &options) { Err(Error::InvalidOptions(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn test_context() {
+        let mut recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let strokes = parse_sample(STROKES_2);
+        let res = recognizer.lookup(&strokes, 4);
+        let mut options = LookupOptions::default();
+        options.context = Some("我".to_string());
+        // Without a model, the context doesn't matter
+        assert_eq!(recognizer.lookup_with(&strokes, 4, &options), res);
+        // A candidate that's outside the top 4 but in the pool is lifted to the top when only it follows the context
+        let pool = recognizer.lookup(&strokes, RERANK_POOL_SIZE);
+        let likely = pool[10].hanzi;
+        let mut model = NgramModel::new();
+        model.add(&['我', likely], 10);
+        recognizer.set_language_model(Some(model));
+        options.context_weight = 1.0;
+        let reranked = recognizer.lookup_with(&strokes, 4, &options);
+        assert_eq!(reranked.len(), 4);
+        assert!(reranked[0].hanzi == likely);
+        assert_eq!(reranked[0].score, pool[10].score);
+        let mut session = recognizer.session();
+        for stroke in parse_sample(STROKES_2) {
+            session.add_stroke(stroke);
+        }
+        assert_eq!(session.lookup_with(4, &options), reranked);
+        // Nor does a context the model doesn't know
+        options.context = Some("你".to_string());
+        assert_eq!(recognizer.lookup_with(&strokes, 4, &options), res);
+        options.context_weight = -0.5;
+        assert!(match recognizer.try_lookup_with(&strokes, 4, &options) { Err(Error::InvalidOptions(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn test_confidence() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let res = recognizer.lookup(&parse_sample(STROKES_2), 8);
+        assert!(res[0].confidence > 0.7);
+        assert!(res.iter().all(|m| m.confidence >= 0.0 && m.confidence <= res[0].confidence));
+        let res = recognizer.lookup(&parse_sample(STROKES_4), 8);
+        assert!(res[0].hanzi == '氣' && res[0].confidence > 0.7);
+        // A perfect drawing is close to 1
+        let wo = recognizer.dataset().templates('我')[0];
+        let res = recognizer.lookup(&draw_template(wo), 8);
+        assert!(res[0].hanzi == '我' && res[0].confidence > 0.9);
+        // Random scrawls still match something, but not with confidence
+        let mut seed: u32 = 12345;
+        let mut next_coord = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            ((seed >> 16) & 0xff) as u8
+        };
+        let mut options = LookupOptions::default();
+        options.min_confidence = 0.6;
+        for stroke_count in 3..8 {
+            let mut scrawl: Vec<Vec<Vec<u8>>> = Vec::new();
+            for _ in 0..stroke_count {
+                scrawl.push((0..4).map(|_| vec![next_coord(), next_coord()]).collect());
+            }
+            let strokes = parse_sample(&serde_json::to_string(&scrawl).unwrap());
+            assert_eq!(recognizer.lookup(&strokes, 8).len(), 8);
+            assert!(recognizer.lookup_with(&strokes, 8, &options).is_empty());
+        }
+        assert_eq!(recognizer.lookup_with(&parse_sample(STROKES_2), 8, &options)[0].hanzi, '十');
+        options.min_confidence = 2.0;
+        assert!(match recognizer.try_lookup_with(&parse_sample(STROKES_2), 8, &options) { Err(Error::InvalidOptions(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn test_prefix() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        // The first 5 strokes of 我's 7
+        let mut strokes = draw_template(recognizer.dataset().templates('我')[0]);
+        strokes.truncate(5);
+        let mut options = LookupOptions::default();
+        assert!(recognizer.lookup_with(&strokes, 8, &options).iter().all(|m| m.hanzi != '我'));
+        options.prefix = true;
+        let res = recognizer.lookup_with(&strokes, 8, &options);
+        let wo = &res[0];
+        assert!(wo.hanzi == '我' && wo.confidence > 0.8);
+        // Characters with fewer strokes than the input are not candidates
+        assert!(res.iter().all(|m| recognizer.dataset().templates(m.hanzi).iter().any(|t| t.stroke_count >= 5)));
+        // The unwritten rest of the template is skipped for free
+        let explanation = recognizer.explain_char(&strokes, '我', &options).unwrap();
+        assert_eq!(explanation.score, wo.score);
+        match *explanation.steps.last().unwrap() {
+            AlignmentStep::SkippedTemplate { penalty, .. } => assert_eq!(penalty, 0.0),
+            _ => panic!("Expected a skipped template substroke."),
+        }
+        // Sessions find the same prefix matches
+        let mut session = recognizer.session();
+        for stroke in strokes {
+            session.add_stroke(stroke);
+        }
+        assert_eq!(session.lookup_with(8, &options), res);
+    }
+
+    #[test]
+    fn test_component() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        // 钅, drawn as the first 5 strokes of 钱
+        let mut strokes = draw_template(recognizer.dataset().templates('钱')[0]);
+        strokes.truncate(5);
+        let res = recognizer.lookup_component(&strokes, 8, &LookupOptions::default());
+        assert_eq!(res.len(), 8);
+        // All simplified characters with the metal radical, which are encoded together
+        assert!(res.iter().all(|m| m.hanzi >= '\u{9485}' && m.hanzi <= '\u{95ff}' && m.confidence > 0.9));
+        // The windows for five strokes are kept for the next lookup
+        assert!(Arc::ptr_eq(&recognizer.component_windows(5), &recognizer.component_windows(5)));
+        // Filters apply as in lookup
+        let mut options = LookupOptions::default();
+        options.filter = Some(CharFilter::allow_str("钱线浅"));
+        let res = recognizer.lookup_component(&strokes, 8, &options);
+        assert!(res[0].hanzi == '钱');
+        assert!(res.iter().all(|m| "钱线浅".contains(m.hanzi)));
+    }
+
+    #[test]
+    fn test_order_free() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        // 国 with every stroke in place, but written last stroke first
+        let mut strokes = draw_template(recognizer.dataset().templates('国')[0]);
+        strokes.reverse();
+        let res = recognizer.lookup(&strokes, 8);
+        assert!(res.iter().all(|m| m.hanzi != '国'));
+        let mut options = LookupOptions::default();
+        options.order_free_weight = 1.0;
+        let res = recognizer.lookup_with(&strokes, 8, &options);
+        assert_eq!(res[0].hanzi, '国');
+        let mut session = recognizer.session();
+        let mut reversed = draw_template(recognizer.dataset().templates('国')[0]);
+        reversed.reverse();
+        for stroke in reversed {
+            session.add_stroke(stroke);
+        }
+        assert_eq!(session.lookup_with(8, &options), res);
+        // Blending keeps the ordered score for what it's worth, so a correctly ordered drawing still wins
+        options.order_free_weight = 0.5;
+        let res = recognizer.lookup_with(&parse_sample(STROKES_2), 8, &options);
+        assert_eq!(res[0].hanzi, '十');
+        options.order_free_weight = 1.5;
+        assert!(match recognizer.try_lookup_with(&strokes, 8, &options) { Err(Error::InvalidOptions(_)) => true, _ => false });
+    }
+
+    // Templates drawn one after another along a line, each in its own 256 x 256 box with a little space in between
+    fn draw_line(recognizer: &Recognizer, text: &str, direction: LineDirection) -> Vec<Stroke<PointF>> {
+        let mut strokes: Vec<Stroke<PointF>> = Vec::new();
+        for (i, hanzi) in text.chars().enumerate() {
+            let offset = i as f32 * 288f32;
+            for stroke in draw_template(recognizer.dataset().templates(hanzi)[0]) {
+                strokes.push(Stroke {
+                    points: stroke.points.iter().map(|point| match direction {
+                        LineDirection::Horizontal => PointF { x: point.x + offset, y: point.y },
+                        LineDirection::Vertical => PointF { x: point.x, y: point.y + offset },
+                    }).collect(),
+                });
+            }
+        }
+        strokes
+    }
+
+    #[test]
+    fn test_line() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let options = LookupOptions::default();
+        // 明 could also be read as 日 and 月, but the two would be unusually narrow characters
+        for &text in ["中国人", "明天见"].iter() {
+            for &direction in [LineDirection::Horizontal, LineDirection::Vertical].iter() {
+                let strokes = draw_line(&recognizer, text, direction);
+                let res = recognizer.lookup_line(&strokes, direction, 4, &options);
+                assert_eq!(res.text(), text);
+                assert!(res.score > 0.8);
+                assert!(res.segments.iter().all(|segment| segment.matches.len() <= 4));
+            }
+        }
+        let strokes = draw_line(&recognizer, "中国人", LineDirection::Horizontal);
+        let res = recognizer.lookup_line(&strokes, LineDirection::Horizontal, 4, &options);
+        let stroke_counts: Vec<(usize, usize)> = res.segments.iter().map(|segment| (segment.first_stroke, segment.stroke_count)).collect();
+        assert_eq!(stroke_counts, [(0, 4), (4, 8), (12, 2)]);
+        // A single character is a line of one
+        let strokes = draw_line(&recognizer, "国", LineDirection::Horizontal);
+        assert_eq!(recognizer.lookup_line(&strokes, LineDirection::Horizontal, 4, &options).text(), "国");
+        assert!(match recognizer.try_lookup_line(&Vec::<Stroke>::new(), LineDirection::Horizontal, 4, &options) { Err(Error::NoStrokes) => true, _ => false });
+    }
+
+    #[test]
+    fn test_write_over() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        // Characters written on top of each other in the same box, without timing
+        for &text in ["中国", "我们", "你好吗"].iter() {
+            let mut write_over = recognizer.write_over();
+            let mut committed = String::new();
+            for hanzi in text.chars() {
+                for stroke in draw_template(recognizer.dataset().templates(hanzi)[0]) {
+                    committed.extend(write_over.add_stroke(stroke).map(|m| m.hanzi));
+                }
+                // The last character is only committed once the next one is started
+                assert_eq!(write_over.text(), committed);
+            }
+            assert_eq!(write_over.candidates()[0].hanzi, text.chars().last().unwrap());
+            assert_eq!(write_over.finish().map(|m| m.hanzi), text.chars().last());
+            assert_eq!(write_over.text(), text);
+            assert!(write_over.strokes().is_empty() && write_over.finish().is_none());
+        }
+        // 十 followed by 一 looks just like 土, unless the writer pauses in between
+        let write_pen_strokes = |pause: f64| {
+            let mut write_over = recognizer.write_over();
+            let mut time = 0f64;
+            for (i, hanzi) in "十一".chars().enumerate() {
+                time += if i == 0 { 0.0 } else { pause };
+                for stroke in draw_template(recognizer.dataset().templates(hanzi)[0]) {
+                    let points = stroke.points.iter().map(|point| {
+                        time += 10.0;
+                        PenPoint { x: point.x, y: point.y, time: Some(time), pressure: None }
+                    }).collect();
+                    write_over.add_stroke(Stroke { points: points });
+                    time += 150.0;
+                }
+            }
+            write_over.finish();
+            write_over.text()
+        };
+        assert_eq!(write_pen_strokes(0.0), "土");
+        assert_eq!(write_pen_strokes(600.0), "十一");
+    }
+
+    #[test]
+    fn test_preprocessing() {
+        let mut recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        // The hand-drawn 十 with a speck where the pen touched the screen by accident
+        let with_speck = || {
+            let mut strokes = parse_sample(STROKES_2);
+            strokes.push(Stroke { points: vec![Point { x: 200, y: 40 }, Point { x: 201, y: 40 }] });
+            strokes
+        };
+        assert!(recognizer.lookup(&with_speck(), 8)[0].hanzi != '十');
+        let mut pipeline = Pipeline::standard();
+        pipeline.push(DropTinyStrokes::default());
+        recognizer.set_preprocessing(Some(pipeline));
+        let res = recognizer.lookup(&with_speck(), 8);
+        assert_eq!(res[0].hanzi, '十');
+        // Sessions clean up their strokes the same way
+        let mut session = recognizer.session();
+        for stroke in with_speck() {
+            session.add_stroke(stroke);
+        }
+        assert_eq!(session.lookup(8), res);
+        // Clean input is still recognized
+        assert_eq!(recognizer.lookup(&parse_sample(STROKES_4), 8)[0].hanzi, '氣');
+        recognizer.set_preprocessing(None);
+        assert!(recognizer.preprocessing().is_none());
+        assert!(recognizer.lookup(&with_speck(), 8)[0].hanzi != '十');
+    }
+
+    #[test]
+    fn test_segmenter() {
+        let mut recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let strokes = parse_sample(STROKES_4);
+        let corner = recognizer.lookup(&strokes, 8);
+        recognizer.set_segmenter(RdpSegmenter::default());
+        let rdp = recognizer.lookup(&strokes, 8);
+        assert_eq!(rdp[0].hanzi, '氣');
+        assert_eq!(recognizer.lookup(&parse_sample(STROKES_2), 8)[0].hanzi, '十');
+        // Sessions split strokes with the recognizer's segmenter too
+        let mut session = recognizer.session();
+        for stroke in parse_sample(STROKES_4) {
+            session.add_stroke(stroke);
+        }
+        assert_eq!(session.lookup(8), rdp);
+        drop(session);
+        recognizer.set_segmenter(CornerSegmenter::default());
+        assert_eq!(recognizer.lookup(&strokes, 8), corner);
+    }
+
+    #[test]
+    fn test_segmenter_scale() {
+        let mut recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        // 氣 as drawn, and at half the size in the canvas's top left corner
+        let scaled = |factor: f32| -> Vec<Stroke<PenPoint>> {
+            parse_sample(STROKES_4).iter().map(|stroke| Stroke {
+                points: stroke.points.iter().map(|pt| PenPoint { x: pt.x() * factor, y: pt.y() * factor, time: None, pressure: None }).collect(),
+            }).collect()
+        };
+        let (large, small) = (scaled(1.0), scaled(0.5));
+        // The input stroke behind each substroke, which tells how the strokes were split
+        let split = |recognizer: &Recognizer, strokes: &[Stroke<PenPoint>]| recognizer.explain_char(strokes, '氣', &LookupOptions::default()).unwrap().input_strokes;
+        recognizer.set_segmenter(CornerSegmenter { scale_to_character: true, ..CornerSegmenter::default() });
+        assert_eq!(split(&recognizer, &small), [0, 1, 2, 3, 3, 3, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(split(&recognizer, &small), split(&recognizer, &large));
+        assert_eq!(recognizer.lookup(&small, 8), recognizer.lookup(&large, 8));
+    }
+
+    #[test]
+    fn test_char_filter() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let strokes = parse_sample(STROKES_2);
+        let mut options = LookupOptions::default();
+        options.filter = Some(CharFilter::allow_str("十千干"));
+        let res = recognizer.lookup_with(&strokes, 8, &options);
+        assert!(res[0].hanzi == '十');
+        assert!(res.len() <= 3 && res.iter().all(|m| "十千干".contains(m.hanzi)));
+        options.filter = Some(CharFilter::deny_str("十"));
+        let res = recognizer.lookup_with(&strokes, 8, &options);
+        assert_eq!(res.len(), 8);
+        assert!(res.iter().all(|m| m.hanzi != '十'));
+        // A session uses the filter too, even with rows cached for the excluded characters
+        let mut session = recognizer.session();
+        for stroke in strokes {
+            session.add_stroke(stroke);
+        }
+        assert!(session.lookup(8)[0].hanzi == '十');
+        assert_eq!(session.lookup_with(8, &options), res);
+    }
+
+    #[test]
+    fn test_explain() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let strokes = parse_sample(STROKES_2);
+        let options = LookupOptions::default();
+        let matches = recognizer.lookup_with(&strokes, 4, &options);
+        let explanations = recognizer.explain(&strokes, 4, &options);
+        assert_eq!(explanations.len(), matches.len());
+        for (explanation, m) in explanations.iter().zip(matches.iter()) {
+            assert!(explanation.hanzi == m.hanzi);
+            assert_eq!(explanation.score, m.score);
+        }
+        // Each stroke of 十 is one substroke, matched in order; the template's short joint between them is skipped
+        let best = &explanations[0];
+        assert_eq!(best.input_strokes, [0, 1]);
+        assert_eq!(best.steps.len(), 3);
+        match best.steps[0] {
+            AlignmentStep::Matched { input: 0, template: 0, scores } => assert!(scores.direction > 0.9 && scores.length > 0.9 && scores.position > 0.9),
+            _ => panic!("Expected a match."),
+        }
+        assert!(match best.steps[1] { AlignmentStep::SkippedTemplate { template: 1, .. } => true, _ => false });
+        assert!(match best.steps[2] { AlignmentStep::Matched { input: 1, template: 2, .. } => true, _ => false });
+        // 一 against 十: the vertical stroke is left over; without the stroke count bonus, the steps add up to the score
+        let explanation = recognizer.explain_char(&strokes, '一', &options).unwrap();
+        let skipped = explanation.steps.iter().filter(|step| match step { AlignmentStep::SkippedInput { input: 1, .. } => true, _ => false }).count();
+        assert_eq!(skipped, 1);
+        let mut total = 0f32;
+        for step in &explanation.steps {
+            total += match step {
+                AlignmentStep::Matched { scores, .. } => scores.total,
+                AlignmentStep::SkippedInput { penalty, .. } => -penalty,
+                AlignmentStep::SkippedTemplate { penalty, .. } => -penalty,
+            };
+        }
+        assert!((total - explanation.score).abs() < 1e-4);
+        // 氣 with two strokes missing is explained even where lookup doesn't consider it
+        let mut strokes = parse_sample(STROKES_4);
+        strokes.remove(4);
+        strokes.remove(4);
+        let explanation = recognizer.explain_char(&strokes, '氣', &LookupOptions::with_looseness(0.0)).unwrap();
+        assert!(explanation.steps.iter().any(|step| match step { AlignmentStep::SkippedTemplate { .. } => true, _ => false }));
+        assert!(recognizer.explain_char(&strokes, 'x', &options).is_none());
+    }
+
+    // Strokes along a template's substrokes, on the 256 x 256 canvas
+    fn draw_template(char_data: &CharData) -> Vec<Stroke<PointF>> {
+        let mut strokes: Vec<Stroke<PointF>> = Vec::new();
+        for (start, end) in char_data.stroke_ranges() {
+            let mut points: Vec<PointF> = Vec::new();
+            for sub_stroke in &char_data.sub_strokes[start..end] {
+                let (from, to) = get_sub_stroke_ends(sub_stroke);
+                if points.is_empty() {
+                    points.push(PointF { x: from.0 * 17f32, y: from.1 * 17f32 });
+                }
+                points.push(PointF { x: to.0 * 17f32, y: to.1 * 17f32 });
+            }
+            strokes.push(Stroke {
+                points: points,
+            });
+        }
+        strokes
+    }
+
+    #[test]
+    fn test_variants() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let standard_chars: Vec<CharData> = recognizer.dataset().chars.iter().filter(|c| c.tag.is_none()).cloned().collect();
+        let standard_only = Recognizer::with_dataset(Dataset::new("standard", "", standard_chars), &MatcherParams::default());
+        // 火 written 人 first: its alternate template wins, and scores better than the standard one could
+        let alt_order = draw_template(recognizer.dataset().templates('火')[1]);
+        let res = recognizer.lookup(&alt_order, 8);
+        assert!(res[0].hanzi == '火');
+        assert_eq!(res[0].tag, Some("alt-order".to_string()));
+        assert_eq!(res.iter().filter(|m| m.hanzi == '火').count(), 1);
+        let standard_res = standard_only.lookup(&alt_order, 8);
+        let standard_score = standard_res.iter().find(|m| m.hanzi == '火').map_or(0f32, |m| m.score);
+        assert!(res[0].score > standard_score);
+        // In standard order, the standard template wins
+        let standard = draw_template(recognizer.dataset().templates('火')[0]);
+        let res = recognizer.lookup(&standard, 8);
+        assert!(res[0].hanzi == '火');
+        assert_eq!(res[0].tag, None);
+        // Explanations and grades are against the best template
+        let options = LookupOptions::default();
+        assert_eq!(recognizer.explain(&alt_order, 1, &options)[0].tag, Some("alt-order".to_string()));
+        assert_eq!(recognizer.explain_char(&alt_order, '火', &options).unwrap().tag, Some("alt-order".to_string()));
+        let grade = recognizer.grade(&alt_order, '火', &options).unwrap();
+        assert_eq!(grade.tag, Some("alt-order".to_string()));
+        assert!(grade.strokes.iter().all(|stroke| stroke.is_correct()));
+    }
+
+    #[test]
+    fn test_regions() {
+        // A made-up Taiwanese form of 十, shaped nothing like it
+        let mut chars = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap().dataset.chars;
+        let mut tw_form = chars.iter().find(|c| c.hanzi == '氣').unwrap().clone();
+        tw_form.hanzi = '十';
+        tw_form.region = Some("TW".to_string());
+        chars.push(tw_form);
+        let recognizer = Recognizer::with_dataset(Dataset::new("regions", "", chars), &MatcherParams::default());
+        let strokes = parse_sample(STROKES_2);
+        let res = recognizer.lookup_with(&strokes, 8, &LookupOptions::default());
+        assert!(res[0].hanzi == '十');
+        assert_eq!(res[0].region, None);
+
+        // Preferring Taiwan keeps the form used everywhere else, at a lower score
+        let mut options = LookupOptions::default();
+        options.region = Some(RegionFilter::Prefer("TW".to_string()));
+        let preferred_res = recognizer.lookup_with(&strokes, 8, &options);
+        let shi = preferred_res.iter().find(|m| m.hanzi == '十').unwrap();
+        assert_eq!(shi.region, None);
+        assert!((shi.score - res[0].score * OTHER_REGION_WEIGHT).abs() < 1e-4);
+        // Only Taiwan's forms: 十 is left out, as its Taiwanese template doesn't fit
+        options.region = Some(RegionFilter::Only("TW".to_string()));
+        assert!(recognizer.lookup_with(&strokes, 8, &options).iter().all(|m| m.hanzi != '十'));
+        // No Japanese forms: the same as without a region
+        options.region = Some(RegionFilter::Only("JP".to_string()));
+        assert_eq!(recognizer.lookup_with(&strokes, 8, &options), res);
+        // A session honors the filter too
+        options.region = Some(RegionFilter::Prefer("TW".to_string()));
+        let mut session = recognizer.session();
+        for stroke in strokes {
+            session.add_stroke(stroke);
+        }
+        assert_eq!(session.lookup_with(8, &options), preferred_res);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        assert_eq!(recognizer.dataset().len(), 9510);
+        let res = recognizer.lookup(&parse_sample(STROKES_2), 4);
+        assert!(res[0].hanzi == '十');
+    }
+}
@@ -0,0 +1,203 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::*;
+
+// Strokes of neighboring characters may overlap along the line by this much of the line's height and still be cut apart
+const MAX_CUT_OVERLAP: f32 = 0.15;
+// A gap this wide, relative to the line's height, always separates two characters
+const SURE_CUT_GAP: f32 = 0.4;
+// A candidate character spans at most this much of the line's height along the line, unless nothing shorter is possible
+const MAX_SEGMENT_LENGTH: f32 = 1.5;
+// Share of a candidate character's confidence lost as its length along the line strays from the line's height.
+// Characters are about square, so this keeps a wide character from being taken for two narrow ones.
+const SHAPE_PENALTY: f32 = 0.2;
+
+// Which way a line of handwriting runs
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LineDirection {
+    // Left to right
+    Horizontal,
+    // Top to bottom
+    Vertical,
+}
+
+// One character position on a line: a run of strokes in writing order, and what they may be
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LineSegment {
+    // Index of the segment's first stroke in the input
+    pub first_stroke: usize,
+    pub stroke_count: usize,
+    // Best first; empty if no character matched well enough
+    pub matches: Vec<Match>,
+}
+
+// The likeliest reading of a line of characters
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LineMatch {
+    pub segments: Vec<LineSegment>,
+    // 0 to 1: the segments' best confidences, weighted by their stroke counts
+    pub score: f32,
+}
+
+impl LineMatch {
+    // Best candidate at each position; positions without candidates are left out
+    pub fn text(&self) -> String {
+        self.segments.iter().filter_map(|segment| segment.matches.first().map(|m| m.hanzi)).collect()
+    }
+}
+
+// Whether the line can be cut between two strokes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Cut {
+    // Strokes on both sides overlap: same character
+    None,
+    Possible,
+    // Wide gap: different characters
+    Sure,
+}
+
+// Where each stroke starts and ends along the line
+pub(crate) fn get_spans<P: InputPoint>(strokes: &[Stroke<P>], direction: LineDirection) -> Vec<(f32, f32)> {
+    strokes.iter().map(|stroke| {
+        let along = stroke.points.iter().map(|point| match direction {
+            LineDirection::Horizontal => point.x(),
+            LineDirection::Vertical => point.y(),
+        });
+        along.fold((f32::MAX, f32::MIN), |(start, end), value| (start.min(value), end.max(value)))
+    }).collect()
+}
+
+// Extent of all strokes across the line, which is about the size of one character
+pub(crate) fn get_line_height<P: InputPoint>(strokes: &[Stroke<P>], direction: LineDirection) -> f32 {
+    let across = strokes.iter().flat_map(|stroke| stroke.points.iter()).map(|point| match direction {
+        LineDirection::Horizontal => point.y(),
+        LineDirection::Vertical => point.x(),
+    });
+    let (top, bottom) = across.fold((f32::MAX, f32::MIN), |(top, bottom), value| (top.min(value), bottom.max(value)));
+    // A line of nothing but flat strokes still needs some height
+    f32::max(bottom - top, 1f32)
+}
+
+// cuts[k] tells if the line can be cut before stroke k; the line's start and end are sure cuts.
+// A cut is possible where everything written before it ends about where everything written after it starts.
+pub(crate) fn get_cuts(spans: &[(f32, f32)], height: f32) -> Vec<Cut> {
+    let mut cuts = vec![Cut::Sure; spans.len() + 1];
+    for k in 1..spans.len() {
+        let end_before = spans[..k].iter().map(|span| span.1).fold(f32::MIN, f32::max);
+        let start_after = spans[k..].iter().map(|span| span.0).fold(f32::MAX, f32::min);
+        let overlap = end_before - start_after;
+        cuts[k] = if overlap <= -SURE_CUT_GAP * height { Cut::Sure } else if overlap <= MAX_CUT_OVERLAP * height { Cut::Possible } else { Cut::None };
+    }
+    cuts
+}
+
+// Finds the split of the strokes into characters whose best matches have the highest confidence, per stroke.
+// lookup(first, end) matches strokes first..end as one character.
+pub(crate) fn segment_line<F>(spans: &[(f32, f32)], height: f32, max_stroke_count: usize, mut lookup: F) -> LineMatch
+    where F: FnMut(usize, usize) -> Vec<Match> {
+    let cuts = get_cuts(spans, height);
+    let stroke_count = spans.len();
+    // For each cut, the best path that ends there: its total, and its last segment's first stroke and matches
+    let mut best: Vec<Option<(f32, usize, Vec<Match>)>> = (0..stroke_count + 1).map(|_| None).collect();
+    best[0] = Some((0f32, 0, Vec::new()));
+    for first in 0..stroke_count {
+        let total = match best[first] {
+            Some((total, _, _)) => total,
+            None => continue,
+        };
+        let mut start = f32::MAX;
+        let mut end = f32::MIN;
+        for next in first + 1..stroke_count + 1 {
+            start = start.min(spans[next - 1].0);
+            end = end.max(spans[next - 1].1);
+            if cuts[next] == Cut::None {
+                continue;
+            }
+            // Up to the next cut is always allowed, so there is a path however the strokes are laid out
+            let shortest = cuts[first + 1..next].iter().all(|&cut| cut == Cut::None);
+            if !shortest && (next - first > max_stroke_count || end - start > MAX_SEGMENT_LENGTH * height) {
+                break;
+            }
+            let matches = lookup(first, next);
+            let confidence = matches.first().map(|m| m.confidence).unwrap_or(0f32);
+            let shape = 1f32 - SHAPE_PENALTY * f32::min(((end - start) / height).ln().abs(), 1f32);
+            let next_total = total + confidence * shape * (next - first) as f32;
+            let better = match best[next] {
+                Some((best_total, _, _)) => next_total > best_total,
+                None => true,
+            };
+            if better {
+                best[next] = Some((next_total, first, matches));
+            }
+            if cuts[next] == Cut::Sure {
+                break;
+            }
+        }
+    }
+    let total = best[stroke_count].as_ref().map(|&(total, _, _)| total).unwrap_or(0f32);
+    // Walk back from the end to collect the segments
+    let mut segments: Vec<LineSegment> = Vec::new();
+    let mut next = stroke_count;
+    while next > 0 {
+        let (_, first, matches) = best[next].take().unwrap();
+        segments.push(LineSegment {
+            first_stroke: first,
+            stroke_count: next - first,
+            matches,
+        });
+        next = first;
+    }
+    segments.reverse();
+    let score = if stroke_count == 0 { 0f32 } else { total / stroke_count as f32 };
+    LineMatch {
+        segments,
+        score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_match(hanzi: char, confidence: f32) -> Match {
+        Match { confidence, ..Match::new(hanzi, confidence * 10.0) }
+    }
+
+    #[test]
+    fn test_cuts() {
+        // Two overlapping strokes, a gap, one stroke touching the next, then a wide gap
+        let spans = [(0.0, 80.0), (20.0, 100.0), (130.0, 200.0), (195.0, 290.0), (400.0, 480.0)];
+        let cuts = get_cuts(&spans, 100.0);
+        assert_eq!(cuts, [Cut::Sure, Cut::None, Cut::Possible, Cut::Possible, Cut::Sure, Cut::Sure]);
+    }
+
+    #[test]
+    fn test_segment_line() {
+        // Strokes 0 and 1 could be one wide character or two narrow ones; stroke 2 stands apart
+        let spans = [(0.0, 45.0), (55.0, 100.0), (200.0, 300.0)];
+        let mut lookups: Vec<(usize, usize)> = Vec::new();
+        let res = segment_line(&spans, 100.0, 48, |first, end| {
+            lookups.push((first, end));
+            match (first, end) {
+                (0, 1) => vec![to_match('日', 0.9)],
+                (1, 2) => vec![to_match('月', 0.9)],
+                (0, 2) => vec![to_match('明', 0.9), to_match('朋', 0.8)],
+                (2, 3) => vec![to_match('天', 0.6)],
+                _ => Vec::new(),
+            }
+        });
+        // Same confidence, but 明 is as wide as the line is high
+        assert_eq!(res.text(), "明天");
+        assert_eq!(res.segments[0].stroke_count, 2);
+        assert_eq!(res.segments[1].first_stroke, 2);
+        assert_eq!(res.segments[0].matches.len(), 2);
+        assert!((res.score - (0.9 * 2.0 + 0.6) / 3.0).abs() < 1e-5);
+        // Nothing spans the wide gap
+        assert!(lookups.iter().all(|&(first, end)| end <= 2 || first == 2));
+        // Unmatched strokes still make a segment
+        let res = segment_line(&spans[..1], 100.0, 48, |_, _| Vec::new());
+        assert_eq!(res.segments.len(), 1);
+        assert_eq!(res.text(), "");
+        assert_eq!(segment_line(&[], 100.0, 48, |_, _| Vec::new()).segments.len(), 0);
+    }
+}
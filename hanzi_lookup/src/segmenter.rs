@@ -0,0 +1,187 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::analyzed_character::*;
+use super::*;
+
+// Splits a stroke into substrokes, the straight pieces that are matched against the dataset.
+// Lengths are given in units of the 256 x 256 reference canvas; scale is how many input units one of them is,
+// from the size of the canvas, or of the character that the stroke belongs to if the segmenter scales to it.
+pub trait Segmenter: Send + Sync {
+    // Indexes of the points where substrokes start and end, in order: the first and last point, and every corner
+    fn pivot_indexes(&self, points: &[PenPoint], scale: f32) -> Vec<usize>;
+
+    // Whether lengths are scaled to the size of the character instead of the canvas, so small and large handwriting
+    // are split alike. The dataset was made with canvas scaling, so this is off unless a segmenter opts in.
+    fn scales_to_character(&self) -> bool {
+        false
+    }
+}
+
+// The original heuristic: a pivot wherever the stroke turns a corner, or has curved for long enough
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct CornerSegmenter {
+    // Pivots closer than this to the previous one are dropped
+    pub min_segment_length: f32,
+    // Corner: the way from the previous point to the next is this much longer than the straight line
+    pub max_local_length_ratio: f32,
+    // Curve: the way since the last pivot is this much longer than the straight line
+    pub max_running_length_ratio: f32,
+    // See Segmenter::scales_to_character
+    pub scale_to_character: bool,
+}
+
+impl Default for CornerSegmenter {
+    fn default() -> CornerSegmenter {
+        CornerSegmenter {
+            min_segment_length: MIN_SEGMENT_LENGTH,
+            max_local_length_ratio: MAX_LOCAL_LENGTH_RATIO,
+            max_running_length_ratio: MAX_RUNNING_LENGTH_RATIO,
+            scale_to_character: false,
+        }
+    }
+}
+
+impl Segmenter for CornerSegmenter {
+    fn pivot_indexes(&self, points: &[PenPoint], scale: f32) -> Vec<usize> {
+        get_corner_pivots(points, self.min_segment_length * scale, self.max_local_length_ratio, self.max_running_length_ratio)
+    }
+
+    fn scales_to_character(&self) -> bool {
+        self.scale_to_character
+    }
+}
+
+// Ramer-Douglas-Peucker simplification: keeps the points the stroke strays furthest from a straight line at,
+// until no point is further than the tolerance from the simplified stroke
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct RdpSegmenter {
+    pub tolerance: f32,
+    // Pivots closer than this to the previous one are dropped, as with CornerSegmenter
+    pub min_segment_length: f32,
+    // See Segmenter::scales_to_character
+    pub scale_to_character: bool,
+}
+
+impl Default for RdpSegmenter {
+    fn default() -> RdpSegmenter {
+        RdpSegmenter {
+            tolerance: 12.0,
+            min_segment_length: MIN_SEGMENT_LENGTH,
+            scale_to_character: false,
+        }
+    }
+}
+
+impl Segmenter for RdpSegmenter {
+    fn scales_to_character(&self) -> bool {
+        self.scale_to_character
+    }
+
+    fn pivot_indexes(&self, points: &[PenPoint], scale: f32) -> Vec<usize> {
+        if points.len() < 2 {
+            return (0..points.len()).collect();
+        }
+        let tolerance = self.tolerance * scale;
+        let mut markers = vec![false; points.len()];
+        markers[0] = true;
+        markers[points.len() - 1] = true;
+        // Spans still to simplify, by their first and last index
+        let mut spans = vec![(0, points.len() - 1)];
+        while let Some((first, last)) = spans.pop() {
+            let mut furthest = (first, 0f32);
+            for i in first + 1..last {
+                let distance = get_line_distance(points[i], points[first], points[last]);
+                if distance > furthest.1 {
+                    furthest = (i, distance);
+                }
+            }
+            if furthest.1 > tolerance {
+                markers[furthest.0] = true;
+                spans.push((first, furthest.0));
+                spans.push((furthest.0, last));
+            }
+        }
+        let pivots: Vec<usize> = (0..points.len()).filter(|&i| markers[i]).collect();
+        merge_close_pivots(points, pivots, self.min_segment_length * scale)
+    }
+}
+
+// Distance of a point from the line through a and b; from a itself if a and b are the same point
+fn get_line_distance(point: PenPoint, a: PenPoint, b: PenPoint) -> f32 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length == 0f32 {
+        return ((point.x - a.x).powi(2) + (point.y - a.y).powi(2)).sqrt();
+    }
+    ((point.x - a.x) * dy - (point.y - a.y) * dx).abs() / length
+}
+
+// Drops pivots too close to the one kept before them; the stroke keeps its first and last point
+fn merge_close_pivots(points: &[PenPoint], pivots: Vec<usize>, min_segment_length: f32) -> Vec<usize> {
+    let distance = |a: usize, b: usize| ((points[a].x - points[b].x).powi(2) + (points[a].y - points[b].y).powi(2)).sqrt();
+    let last = *pivots.last().unwrap();
+    let mut res: Vec<usize> = Vec::with_capacity(pivots.len());
+    for ix in pivots {
+        if ix == last {
+            // A short segment at the end gives way to the last point, unless only the first point is left
+            if res.len() > 1 && distance(res[res.len() - 1], ix) < min_segment_length {
+                res.pop();
+            }
+            res.push(ix);
+        } else if res.is_empty() || distance(res[res.len() - 1], ix) >= min_segment_length {
+            res.push(ix);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Points every 4 units from each corner to the next
+    fn polyline(corners: &[(f32, f32)]) -> Vec<PenPoint> {
+        let mut points = vec![PenPoint { x: corners[0].0, y: corners[0].1, time: None, pressure: None }];
+        for pair in corners.windows(2) {
+            let (dx, dy) = (pair[1].0 - pair[0].0, pair[1].1 - pair[0].1);
+            let steps = ((dx * dx + dy * dy).sqrt() / 4f32).ceil() as usize;
+            for step in 1..steps + 1 {
+                let t = step as f32 / steps as f32;
+                points.push(PenPoint { x: pair[0].0 + t * dx, y: pair[0].1 + t * dy, time: None, pressure: None });
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn test_rdp() {
+        let segmenter = RdpSegmenter::default();
+        // 乛: right, then down left
+        let points = polyline(&[(20.0, 20.0), (120.0, 20.0), (60.0, 100.0)]);
+        assert_eq!(segmenter.pivot_indexes(&points, 1.0), [0, 25, points.len() - 1]);
+        // A wobble within the tolerance is still a straight line
+        let points = polyline(&[(20.0, 20.0), (70.0, 24.0), (120.0, 20.0)]);
+        assert_eq!(segmenter.pivot_indexes(&points, 1.0), [0, points.len() - 1]);
+        // A short tick at the end is merged into the last segment
+        let points = polyline(&[(20.0, 20.0), (120.0, 20.0), (124.0, 28.0)]);
+        assert_eq!(segmenter.pivot_indexes(&points, 1.0), [0, points.len() - 1]);
+        // A tap and nothing at all
+        assert_eq!(segmenter.pivot_indexes(&points[..1], 1.0), [0]);
+        assert_eq!(segmenter.pivot_indexes(&[], 1.0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_scale() {
+        // The same three-legged stroke drawn twice as big, with twice as many points, has its corners at the same points
+        let small = polyline(&[(20.0, 20.0), (60.0, 20.0), (60.0, 60.0), (24.0, 56.0)]);
+        let large: Vec<PenPoint> = polyline(&[(40.0, 40.0), (120.0, 40.0), (120.0, 120.0), (48.0, 112.0)]);
+        let corner = CornerSegmenter::default();
+        let rdp = RdpSegmenter::default();
+        assert_eq!(corner.pivot_indexes(&small, 0.5), [0, 10, 20, small.len() - 1]);
+        assert_eq!(corner.pivot_indexes(&large, 1.0), [0, 20, 40, large.len() - 1]);
+        assert_eq!(rdp.pivot_indexes(&small, 0.5), [0, 10, 20, small.len() - 1]);
+        assert_eq!(rdp.pivot_indexes(&large, 1.0), [0, 20, 40, large.len() - 1]);
+    }
+}
@@ -0,0 +1,178 @@
+use super::analyzed_character::*;
+use super::error::*;
+use super::matcher::*;
+use super::recognizer::*;
+use super::*;
+
+// Recognition of a character while it is being written, one stroke at a time.
+// Keeps each stroke's pivot points and the DP rows of every candidate, so a lookup after a new stroke
+// only computes rows for the new substrokes. Rows are recomputed in full when the new stroke changes the
+// character's bounding box, because that renormalizes the earlier substrokes too, and when the input has grown
+// enough to widen the range of substrokes compared, because that adds cells to every row.
+pub struct Session<'a, P = Point> {
+    recognizer: &'a Recognizer,
+    strokes: Vec<Stroke<P>>,
+    canvas_size: f32,
+    // Pivots of the first strokes, found at pivot_scale; all are found again when the scale changes
+    pivot_indexes: Vec<Vec<usize>>,
+    pivot_scale: f32,
+    cache: ScoreCache,
+}
+
+impl<'a, P: InputPoint> Session<'a, P> {
+    pub fn new(recognizer: &'a Recognizer) -> Session<'a, P> {
+        Session {
+            recognizer,
+            strokes: Vec::new(),
+            canvas_size: REFERENCE_CANVAS_SIZE,
+            pivot_indexes: Vec::new(),
+            pivot_scale: 0f32,
+            cache: ScoreCache::new(),
+        }
+    }
+
+    // Size of the square canvas the strokes are drawn on; lookup_with also sets it from its options
+    pub fn set_canvas_size(&mut self, canvas_size: f32) {
+        self.canvas_size = canvas_size;
+    }
+
+    pub fn add_stroke(&mut self, stroke: Stroke<P>) {
+        self.strokes.push(stroke);
+    }
+
+    // Removes the last stroke; cached rows for the remaining strokes stay usable
+    pub fn undo_stroke(&mut self) -> Option<Stroke<P>> {
+        self.pivot_indexes.truncate(self.strokes.len().saturating_sub(1));
+        self.strokes.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.strokes.clear();
+        self.pivot_indexes.clear();
+        self.cache.clear();
+    }
+
+    pub fn strokes(&self) -> &[Stroke<P>] {
+        &self.strokes
+    }
+
+    // Same result as the recognizer's lookup on the session's strokes
+    pub fn lookup(&mut self, limit: usize) -> Vec<Match> {
+        let options = self.default_options();
+        self.lookup_with(limit, &options)
+    }
+
+    pub fn lookup_with(&mut self, limit: usize, options: &LookupOptions) -> Vec<Match> {
+        self.set_canvas_size(options.canvas_size);
+        let recognizer = self.recognizer;
+        // Preprocessing may change earlier strokes as new ones come in, e.g. by joining them, so it runs on all strokes
+        // every time. The cached rows are still used as far as the substrokes stay the same.
+        match recognizer.preprocessing() {
+            Some(pipeline) => {
+                let strokes = pipeline.run(&self.strokes, self.canvas_size);
+                let input_char = AnalyzedCharacter::from_strokes_with(&strokes, self.canvas_size, recognizer.segmenter());
+                lookup_session(recognizer, &input_char, limit, options, &mut self.cache)
+            }
+            None => {
+                self.update_pivots();
+                let input_char = AnalyzedCharacter::from_strokes_with_pivots(&self.strokes, self.pivot_indexes.clone(), self.canvas_size);
+                lookup_session(recognizer, &input_char, limit, options, &mut self.cache)
+            }
+        }
+    }
+
+    pub fn try_lookup(&mut self, limit: usize) -> Result<Vec<Match>, Error> {
+        let options = self.default_options();
+        self.try_lookup_with(limit, &options)
+    }
+
+    pub fn try_lookup_with(&mut self, limit: usize, options: &LookupOptions) -> Result<Vec<Match>, Error> {
+        if limit == 0 {
+            return Err(Error::ZeroLimit);
+        }
+        check_options(options)?;
+        check_strokes(&self.strokes)?;
+        Ok(self.lookup_with(limit, options))
+    }
+
+    // Finds the pivots of new strokes, or of all strokes if they changed the character's size or the canvas did
+    fn update_pivots(&mut self) {
+        let segmenter = self.recognizer.segmenter();
+        let scale = get_pivot_scale(&self.strokes, self.canvas_size, segmenter);
+        if scale != self.pivot_scale {
+            self.pivot_scale = scale;
+            self.pivot_indexes.clear();
+        }
+        for stroke in &self.strokes[self.pivot_indexes.len()..] {
+            self.pivot_indexes.push(get_pivot_indexes(stroke, segmenter, scale));
+        }
+    }
+
+    fn default_options(&self) -> LookupOptions {
+        let mut options = LookupOptions::with_looseness(self.recognizer.params().DEFAULT_LOOSENESS);
+        options.canvas_size = self.canvas_size;
+        options
+    }
+}
+
+fn lookup_session<P: InputPoint>(recognizer: &Recognizer, input_char: &AnalyzedCharacter<P>, limit: usize, options: &LookupOptions, cache: &mut ScoreCache) -> Vec<Match> {
+    let weights = recognizer.template_weights(options);
+    recognizer.lookup_ranked(limit, options, |pool_size| {
+        lookup_widening(options, input_char.sub_stroke_count, |looseness| recognizer.lookup_analyzed(input_char, pool_size, recognizer.lookup_context(looseness, options, weights.as_ref(), Some(&mut *cache))))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_samples::*;
+
+    #[test]
+    fn test_incremental() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let mut session = recognizer.session();
+        let mut written: Vec<Stroke> = Vec::new();
+        for stroke in parse_sample(STROKES_4) {
+            written.push(Stroke { points: stroke.points.clone() });
+            session.add_stroke(stroke);
+            assert_eq!(session.lookup(8), recognizer.lookup(&written, 8));
+        }
+        assert!(session.lookup(8)[0].hanzi == '氣');
+        // Undo, then redo the same stroke
+        let last = session.undo_stroke().unwrap();
+        written.pop();
+        assert_eq!(session.lookup(8), recognizer.lookup(&written, 8));
+        session.add_stroke(last);
+        assert!(session.lookup(8)[0].hanzi == '氣');
+        // Widening through the session gives the same as without
+        let mut options = LookupOptions::with_looseness(0.0);
+        options.auto_widen = Some(AutoWiden::default());
+        assert_eq!(session.lookup_with(8, &options), recognizer.lookup_with(&parse_sample(STROKES_4), 8, &options));
+        session.clear();
+        assert_eq!(session.strokes().len(), 0);
+        assert_eq!(session.lookup(8).len(), 0);
+        assert!(match session.try_lookup(8) { Err(Error::NoStrokes) => true, _ => false });
+    }
+
+    #[test]
+    fn test_row_reuse() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let mut session = recognizer.session();
+        // Strokes 6 to 8 and 10 of 氣 stay inside the strokes before them and don't widen the substroke range,
+        // so the rows of all earlier substrokes are taken over; the others start from scratch
+        let mut reused: Vec<usize> = Vec::new();
+        for stroke in parse_sample(STROKES_4) {
+            session.add_stroke(stroke);
+            session.lookup(8);
+            reused.push(session.cache.reused_rows());
+        }
+        assert_eq!(reused, [0, 0, 0, 0, 0, 8, 9, 10, 0, 12]);
+        // Undo and redo keep the rows of the strokes that stay
+        let last = session.undo_stroke().unwrap();
+        session.lookup(8);
+        assert_eq!(session.cache.reused_rows(), 12);
+        session.add_stroke(last);
+        session.lookup(8);
+        assert_eq!(session.cache.reused_rows(), 12);
+    }
+}
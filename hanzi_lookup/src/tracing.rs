@@ -0,0 +1,360 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::analyzed_character::*;
+use super::dataset::*;
+use super::entities::*;
+use super::grading::*;
+use super::matcher::*;
+use super::recognizer::*;
+use super::*;
+
+// A traced stroke less similar than this to the expected stroke is not accepted
+const MIN_TRACE_SIMILARITY: f32 = 0.5;
+// Nor one whose line from start to end points off from the template's by more than this, in radians
+const MAX_TRACE_ANGLE: f32 = 0.5;
+// Template strokes whose start and end are closer than this on the 16 x 16 grid are not checked for direction
+const MIN_TEMPLATE_SPAN: f32 = 1.5;
+
+// Where the writer should draw a stroke next, from the template's substrokes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StrokeHint {
+    // Index of the stroke in the template
+    pub template: usize,
+    pub sub_strokes: Vec<SubStrokeHint>,
+}
+
+// One template substroke; positions are on the canvas scaled to 0..1, with Y growing downwards
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SubStrokeHint {
+    // Radians, counterclockwise from pointing right as seen on screen
+    pub direction: f32,
+    // Share of the canvas side
+    pub length: f32,
+    pub center: (f32, f32),
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+}
+
+// What became of one traced stroke
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TraceStep {
+    pub accepted: bool,
+    // The template stroke that was expected; None if the character was already complete
+    pub template: Option<usize>,
+    // Similarity to the expected stroke, about 0 to 1
+    pub similarity: f32,
+    // Why the stroke was not accepted; empty if it was
+    pub faults: Vec<StrokeFault>,
+}
+
+// How an input stroke compares to one template stroke
+struct Fit {
+    similarity: f32,
+    reversed: bool,
+    in_direction: bool,
+    in_place: bool,
+}
+
+impl Fit {
+    fn is_good_shape(&self) -> bool {
+        self.similarity >= MIN_TRACE_SIMILARITY && self.in_direction
+    }
+
+    // Acceptable as this stroke
+    fn is_good(&self) -> bool {
+        self.is_good_shape() && !self.reversed && self.in_place
+    }
+}
+
+// Guided writing of one character, one stroke at a time.
+// Each stroke is checked against the template stroke that comes next; accepted strokes are kept, others dropped,
+// so the writer retries the same stroke. Strokes are expected on a canvas that shows the character filling it.
+pub struct Tracer<'a, P = Point> {
+    recognizer: &'a Recognizer,
+    char_data: &'a CharData,
+    // Substroke ranges of the template's strokes
+    template_strokes: Vec<(usize, usize)>,
+    // Accepted strokes only
+    strokes: Vec<Stroke<P>>,
+    canvas_size: f32,
+}
+
+impl<'a, P: InputPoint> Tracer<'a, P> {
+    // Traces the target's standard template; None if the recognizer's dataset doesn't have the target
+    pub fn new(recognizer: &'a Recognizer, target: char) -> Option<Tracer<'a, P>> {
+        let char_data = *recognizer.dataset().templates(target).first()?;
+        Some(Tracer {
+            recognizer,
+            char_data,
+            template_strokes: char_data.stroke_ranges(),
+            strokes: Vec::new(),
+            canvas_size: REFERENCE_CANVAS_SIZE,
+        })
+    }
+
+    // Size of the square canvas the strokes are drawn on
+    pub fn set_canvas_size(&mut self, canvas_size: f32) {
+        self.canvas_size = canvas_size;
+    }
+
+    pub fn hanzi(&self) -> char {
+        self.char_data.hanzi
+    }
+
+    pub fn stroke_count(&self) -> usize {
+        self.template_strokes.len()
+    }
+
+    // The strokes accepted so far
+    pub fn strokes(&self) -> &[Stroke<P>] {
+        &self.strokes
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.strokes.len() >= self.template_strokes.len()
+    }
+
+    // The stroke to draw next; None once the character is complete
+    pub fn next_hint(&self) -> Option<StrokeHint> {
+        self.hint(self.strokes.len())
+    }
+
+    // Any of the template's strokes, such as to show the whole character
+    pub fn hint(&self, template: usize) -> Option<StrokeHint> {
+        let &(start, end) = self.template_strokes.get(template)?;
+        Some(StrokeHint {
+            template,
+            sub_strokes: self.char_data.sub_strokes[start..end].iter().map(get_sub_stroke_hint).collect(),
+        })
+    }
+
+    // Checks the stroke against the expected one and keeps it if accepted.
+    // Returns None for a stroke that analysis ignores, such as a slip of the pen; it is dropped, but not rejected.
+    pub fn add_stroke(&mut self, stroke: Stroke<P>) -> Option<TraceStep> {
+        let single = vec![stroke];
+        let step = {
+            let input_char = AnalyzedCharacter::from_strokes_on_canvas(&single, self.canvas_size, self.recognizer.segmenter());
+            let analyzed_stroke = input_char.analyzed_strokes.first()?;
+            if analyzed_stroke.sub_strokes.is_empty() {
+                return None;
+            }
+            self.check_stroke(analyzed_stroke)
+        };
+        if step.accepted {
+            self.strokes.extend(single);
+        }
+        Some(step)
+    }
+
+    // Removes the last accepted stroke, so the one before it is expected again
+    pub fn undo_stroke(&mut self) -> Option<Stroke<P>> {
+        self.strokes.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.strokes.clear();
+    }
+
+    fn check_stroke(&self, analyzed_stroke: &AnalyzedStroke<P>) -> TraceStep {
+        let expected = self.strokes.len();
+        if expected >= self.template_strokes.len() {
+            return TraceStep {
+                accepted: false,
+                template: None,
+                similarity: 0f32,
+                faults: vec![StrokeFault::Extra],
+            };
+        }
+        let backwards = reverse_sub_strokes(&analyzed_stroke.sub_strokes);
+        let fit = self.fit_stroke(analyzed_stroke, &backwards, expected);
+        let mut faults: Vec<StrokeFault> = Vec::new();
+        // Only a stroke that doesn't pass as the expected one, but passes as one that comes later, is out of order
+        let skipped_ahead = !fit.is_good() && (expected + 1..self.template_strokes.len())
+            .any(|later| self.fit_stroke(analyzed_stroke, &backwards, later).is_good());
+        if skipped_ahead {
+            faults.push(StrokeFault::WrongOrder);
+        }
+        else {
+            if !fit.is_good_shape() {
+                faults.push(StrokeFault::WrongShape);
+            }
+            if fit.reversed {
+                faults.push(StrokeFault::Reversed);
+            }
+            if !fit.in_place {
+                faults.push(StrokeFault::WrongPosition);
+            }
+        }
+        TraceStep {
+            accepted: faults.is_empty(),
+            template: Some(expected),
+            similarity: fit.similarity.clamp(0f32, 1f32),
+            faults,
+        }
+    }
+
+    fn fit_stroke(&self, analyzed_stroke: &AnalyzedStroke<P>, backwards: &[SubStroke], template: usize) -> Fit {
+        let matcher = self.recognizer.matcher();
+        let template = self.template_sub_strokes(template);
+        let (similarity, reversed) = compare_both_ways(matcher, &analyzed_stroke.sub_strokes, backwards, template);
+        let input_center = get_input_center(&analyzed_stroke.sub_strokes);
+        let template_center = get_template_center(template);
+        let in_place = matcher.position_score(input_center.0 - template_center.0, input_center.1 - template_center.1) >= MIN_POSITION_SCORE;
+
+        // From first point to last, which the substroke scores are lenient about
+        let first = analyzed_stroke.points[0];
+        let last = analyzed_stroke.points[analyzed_stroke.points.len() - 1];
+        let (start, _) = get_sub_stroke_ends(&template[0]);
+        let (_, end) = get_sub_stroke_ends(&template[template.len() - 1]);
+        let mut input_angle = (last.y() - first.y()).atan2(last.x() - first.x());
+        if reversed {
+            input_angle += std::f32::consts::PI;
+        }
+        let mut angle_difference = (input_angle - (end.1 - start.1).atan2(end.0 - start.0)).abs() % (std::f32::consts::PI * 2f32);
+        if angle_difference > std::f32::consts::PI {
+            angle_difference = std::f32::consts::PI * 2f32 - angle_difference;
+        }
+        // A stroke that ends about where it starts has no direction to speak of
+        let template_span = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+        Fit {
+            similarity,
+            reversed,
+            in_direction: template_span < MIN_TEMPLATE_SPAN || angle_difference <= MAX_TRACE_ANGLE,
+            in_place,
+        }
+    }
+
+    fn template_sub_strokes(&self, template: usize) -> &'a [SubStrokeTriple] {
+        let (start, end) = self.template_strokes[template];
+        &self.char_data.sub_strokes[start..end]
+    }
+}
+
+// Decodes a template substroke; centers are on a 16 x 16 grid whose last line is the canvas edge
+fn get_sub_stroke_hint(sub_stroke: &SubStrokeTriple) -> SubStrokeHint {
+    let (start, end) = get_sub_stroke_ends(sub_stroke);
+    let scale = |pt: (f32, f32)| (pt.0 / 15f32, pt.1 / 15f32);
+    SubStrokeHint {
+        direction: sub_stroke.dir as f32 * std::f32::consts::PI * 2f32 / 256f32,
+        length: sub_stroke.length as f32 / 255f32,
+        center: scale(((sub_stroke.center >> 4) as f32, (sub_stroke.center & 0x0f) as f32)),
+        start: scale(start),
+        end: scale(end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_samples::*;
+
+    fn faults(step: Option<TraceStep>) -> Vec<StrokeFault> {
+        step.unwrap().faults
+    }
+
+    #[test]
+    fn test_hints() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        assert!(recognizer.tracer::<Point>('x').is_none());
+        let tracer = recognizer.tracer::<Point>('十').unwrap();
+        assert_eq!(tracer.stroke_count(), 2);
+        // The horizontal first: pointing right, across most of the canvas, a little above the middle
+        let hint = tracer.next_hint().unwrap();
+        assert_eq!(hint.template, 0);
+        assert_eq!(hint.sub_strokes.len(), 1);
+        let horizontal = hint.sub_strokes[0];
+        assert!(horizontal.direction.abs() < 0.2);
+        assert!(horizontal.length > 0.5);
+        assert!(horizontal.start.0 < horizontal.end.0);
+        assert!(horizontal.center.1 > 0.3 && horizontal.center.1 < 0.5);
+        // Then the vertical, pointing down, after its short tick
+        let vertical = tracer.hint(1).unwrap();
+        assert_eq!(vertical.sub_strokes.len(), 2);
+        let main = vertical.sub_strokes[1];
+        assert!((main.direction - std::f32::consts::PI * 1.5).abs() < 0.2);
+        assert!(main.start.1 < main.end.1);
+        assert!(tracer.hint(2).is_none());
+    }
+
+    // Stroke along a hint's substrokes, as a writer tracing the template would draw it
+    fn trace_hint(hint: &StrokeHint) -> Stroke<PointF> {
+        let mut corners = vec![hint.sub_strokes[0].start];
+        corners.extend(hint.sub_strokes.iter().map(|sub_stroke| sub_stroke.end));
+        let mut points: Vec<PointF> = Vec::new();
+        for i in 1..corners.len() {
+            for step in 0..8 {
+                let ratio = step as f32 / 8f32;
+                points.push(PointF {
+                    x: (corners[i - 1].0 + (corners[i].0 - corners[i - 1].0) * ratio) * 256f32,
+                    y: (corners[i - 1].1 + (corners[i].1 - corners[i - 1].1) * ratio) * 256f32,
+                });
+            }
+        }
+        let last = corners[corners.len() - 1];
+        points.push(PointF { x: last.0 * 256f32, y: last.1 * 256f32 });
+        Stroke {
+            points: points,
+        }
+    }
+
+    #[test]
+    fn test_tracing() {
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        let mut tracer = recognizer.tracer('十').unwrap();
+
+        // Vertical first
+        let mut strokes = parse_sample(STROKES_2);
+        let vertical = strokes.pop().unwrap();
+        let horizontal = strokes.pop().unwrap();
+        assert_eq!(faults(tracer.add_stroke(Stroke { points: vertical.points.clone() })), [StrokeFault::WrongOrder]);
+        assert_eq!(tracer.strokes().len(), 0);
+        // Rising to the right: neither stroke goes that way
+        let diagonal = Stroke { points: vec![Point { x: 60, y: 200 }, Point { x: 200, y: 60 }] };
+        assert_eq!(faults(tracer.add_stroke(diagonal)), [StrokeFault::WrongShape]);
+        // The horizontal, but at the very top
+        let moved = Stroke { points: horizontal.points.iter().map(|pt| Point { x: pt.x, y: pt.y - 110 }).collect() };
+        assert_eq!(faults(tracer.add_stroke(moved)), [StrokeFault::WrongPosition]);
+
+        let step = tracer.add_stroke(horizontal).unwrap();
+        assert!(step.accepted);
+        assert_eq!(step.template, Some(0));
+        assert_eq!(tracer.next_hint().unwrap().template, 1);
+        // Vertical drawn bottom up
+        let mut reversed = Stroke { points: vertical.points.clone() };
+        reversed.points.reverse();
+        assert_eq!(faults(tracer.add_stroke(reversed)), [StrokeFault::Reversed]);
+        assert!(tracer.add_stroke(vertical).unwrap().accepted);
+        assert!(tracer.is_complete());
+        assert!(tracer.next_hint().is_none());
+        assert_eq!(faults(tracer.add_stroke(Stroke { points: vec![Point { x: 60, y: 60 }, Point { x: 200, y: 60 }] })), [StrokeFault::Extra]);
+
+        tracer.undo_stroke();
+        assert_eq!(tracer.next_hint().unwrap().template, 1);
+        tracer.clear();
+        assert_eq!(tracer.next_hint().unwrap().template, 0);
+    }
+
+    #[test]
+    fn test_trace_hints() {
+        // Following the hints stroke by stroke writes the whole character
+        let recognizer = Recognizer::from_bytes(include_bytes!("../data/mmah.bin")).unwrap();
+        for &hanzi in &['氣', '口', '我', '永'] {
+            let mut tracer = recognizer.tracer(hanzi).unwrap();
+            while let Some(hint) = tracer.next_hint() {
+                let step = tracer.add_stroke(trace_hint(&hint)).unwrap();
+                assert!(step.accepted, "{} stroke {}: {:?}", hanzi, hint.template, step.faults);
+            }
+            assert_eq!(tracer.strokes().len(), tracer.stroke_count());
+        }
+        // On a larger canvas too
+        let mut tracer = recognizer.tracer('十').unwrap();
+        tracer.set_canvas_size(1024f32);
+        let hint = tracer.next_hint().unwrap();
+        let mut stroke = trace_hint(&hint);
+        for point in stroke.points.iter_mut() {
+            point.x *= 4f32;
+            point.y *= 4f32;
+        }
+        assert!(tracer.add_stroke(stroke).unwrap().accepted);
+    }
+}
@@ -0,0 +1,211 @@
+use super::*;
+
+// Without a pause, a character is only committed once its best match is at least this confident,
+// and the next stroke leaves the strokes less confidently the start of any character by more than this
+const DEFAULT_MIN_COMMIT_CONFIDENCE: f32 = 0.7;
+const DEFAULT_CONFIDENCE_DROP: f32 = 0.03;
+// Milliseconds between two strokes that always separate two characters
+const DEFAULT_NEW_CHARACTER_PAUSE: f64 = 500.0;
+// A stroke with less than this share of its points on the current character's bounding box adds to the character:
+// in write-over input, the next character is written on top of the previous one
+const MIN_OVERLAP_SHARE: f32 = 0.5;
+// The bounding box is widened by this much of the canvas on each side, so thin characters like 一 can be written over
+const OVERLAP_SLACK: f32 = 0.1;
+
+// Settings for write-over input
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteOverOptions {
+    // How each character's candidates are looked up
+    pub lookup: LookupOptions,
+    // Candidates kept for the character being written
+    pub limit: usize,
+    // Milliseconds between strokes after which the next stroke starts a new character; None to ignore timing
+    pub new_character_pause: Option<f64>,
+    // Without a pause, a stroke starts a new character if it lies over the current one and starts back at its top left,
+    // the current character's best match is at least this confident,
+    pub min_commit_confidence: f32,
+    // and adding the stroke leaves the best character the strokes could be the start of less confident by more than this
+    pub confidence_drop: f32,
+}
+
+impl Default for WriteOverOptions {
+    fn default() -> WriteOverOptions {
+        WriteOverOptions {
+            lookup: LookupOptions::default(),
+            limit: 8,
+            new_character_pause: Some(DEFAULT_NEW_CHARACTER_PAUSE),
+            min_commit_confidence: DEFAULT_MIN_COMMIT_CONFIDENCE,
+            confidence_drop: DEFAULT_CONFIDENCE_DROP,
+        }
+    }
+}
+
+// Continuous input where each character is written on top of the previous one, in the same box.
+// Decides stroke by stroke whether a new stroke starts a new character, and if so commits the previous
+// character's top candidate.
+pub struct WriteOver<'a, P = Point> {
+    // Strokes of the character being written
+    session: Session<'a, P>,
+    options: WriteOverOptions,
+    // Best matches for the session's strokes
+    candidates: Vec<Match>,
+    committed: Vec<Match>,
+}
+
+impl<'a, P: InputPoint> WriteOver<'a, P> {
+    pub fn new(recognizer: &'a Recognizer) -> WriteOver<'a, P> {
+        WriteOver::with_options(recognizer, WriteOverOptions::default())
+    }
+
+    pub fn with_options(recognizer: &'a Recognizer, options: WriteOverOptions) -> WriteOver<'a, P> {
+        WriteOver {
+            session: recognizer.session(),
+            options,
+            candidates: Vec::new(),
+            committed: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &WriteOverOptions {
+        &self.options
+    }
+
+    // Adds the next stroke. If it starts a new character, the previous character is committed,
+    // and its top candidate is returned; None if the stroke continues the current character,
+    // or if nothing matched the previous one.
+    pub fn add_stroke(&mut self, stroke: Stroke<P>) -> Option<Match> {
+        if self.session.strokes().is_empty() {
+            self.session.add_stroke(stroke);
+            self.candidates = self.lookup();
+            return None;
+        }
+        let paused = self.is_pause_before(&stroke);
+        let over_current = self.is_over_current(&stroke);
+        let restarted = self.is_restart(&stroke);
+        let confidence_before = get_best_confidence(&self.candidates);
+        self.session.add_stroke(stroke);
+        if !paused {
+            let can_commit = over_current && restarted && confidence_before >= self.options.min_commit_confidence;
+            // Many characters begin with the strokes of another, so the strokes so far only need to be the start of some character
+            if !can_commit || self.lookup_prefix() >= confidence_before - self.options.confidence_drop {
+                self.candidates = self.lookup();
+                return None;
+            }
+        }
+        // The stroke belongs to the next character
+        let stroke = self.session.undo_stroke().unwrap();
+        let committed = self.commit();
+        self.session.add_stroke(stroke);
+        self.candidates = self.lookup();
+        committed
+    }
+
+    // Commits the character being written, when the writer is done; returns its top candidate as add_stroke does
+    pub fn finish(&mut self) -> Option<Match> {
+        if self.session.strokes().is_empty() {
+            return None;
+        }
+        self.commit()
+    }
+
+    // Best matches for the character being written
+    pub fn candidates(&self) -> &[Match] {
+        &self.candidates
+    }
+
+    // Strokes of the character being written
+    pub fn strokes(&self) -> &[Stroke<P>] {
+        self.session.strokes()
+    }
+
+    // Top candidates of the characters committed so far
+    pub fn committed(&self) -> &[Match] {
+        &self.committed
+    }
+
+    pub fn text(&self) -> String {
+        self.committed.iter().map(|m| m.hanzi).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.session.clear();
+        self.candidates.clear();
+        self.committed.clear();
+    }
+
+    fn lookup(&mut self) -> Vec<Match> {
+        self.session.lookup_with(self.options.limit, &self.options.lookup)
+    }
+
+    // Confidence of the best character that the strokes are the beginning of
+    fn lookup_prefix(&mut self) -> f32 {
+        let mut options = self.options.lookup.clone();
+        options.prefix = true;
+        get_best_confidence(&self.session.lookup_with(1, &options))
+    }
+
+    fn commit(&mut self) -> Option<Match> {
+        let top = self.candidates.first().cloned();
+        if let Some(ref top) = top {
+            self.committed.push(top.clone());
+        }
+        self.session.clear();
+        self.candidates.clear();
+        top
+    }
+
+    // True if the writer paused long enough before the stroke; false if the points have no timestamps
+    fn is_pause_before(&self, stroke: &Stroke<P>) -> bool {
+        let pause = match self.options.new_character_pause {
+            Some(pause) => pause,
+            None => return false,
+        };
+        let last_end = self.session.strokes().last().and_then(|last| last.points.last()).and_then(|point| point.time());
+        let start = stroke.points.first().and_then(|point| point.time());
+        match (last_end, start) {
+            (Some(last_end), Some(start)) => start - last_end >= pause,
+            _ => false,
+        }
+    }
+
+    // True if enough of the stroke lies on the current character to be the start of one written over it
+    fn is_over_current(&self, stroke: &Stroke<P>) -> bool {
+        if stroke.points.is_empty() {
+            return false;
+        }
+        let (left, top, right, bottom) = get_bounds(self.session.strokes());
+        let slack = OVERLAP_SLACK * self.options.lookup.canvas_size;
+        let inside = stroke.points.iter().filter(|point| {
+            point.x() >= left - slack && point.x() <= right + slack && point.y() >= top - slack && point.y() <= bottom + slack
+        }).count();
+        inside as f32 >= MIN_OVERLAP_SHARE * stroke.points.len() as f32
+    }
+
+    // True if the stroke starts in the top left of the current character, after the last stroke ended in its bottom or right half
+    fn is_restart(&self, stroke: &Stroke<P>) -> bool {
+        let (left, top, right, bottom) = get_bounds(self.session.strokes());
+        let relative = |point: &P| ((point.x() - left) / f32::max(right - left, 1f32), (point.y() - top) / f32::max(bottom - top, 1f32));
+        let last_end = self.session.strokes().last().and_then(|last| last.points.last()).map(&relative);
+        let start = stroke.points.first().map(relative);
+        match (last_end, start) {
+            (Some(last_end), Some(start)) => start.0 < 0.5 && start.1 < 0.5 && (last_end.0 > 0.5 || last_end.1 > 0.5),
+            _ => false,
+        }
+    }
+}
+
+fn get_best_confidence(matches: &[Match]) -> f32 {
+    matches.first().map(|m| m.confidence).unwrap_or(0f32)
+}
+
+// Left, top, right and bottom of the strokes' points
+fn get_bounds<P: InputPoint>(strokes: &[Stroke<P>]) -> (f32, f32, f32, f32) {
+    let mut bounds = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    for point in strokes.iter().flat_map(|stroke| stroke.points.iter()) {
+        bounds.0 = bounds.0.min(point.x());
+        bounds.1 = bounds.1.min(point.y());
+        bounds.2 = bounds.2.max(point.x());
+        bounds.3 = bounds.3.max(point.y());
+    }
+    bounds
+}
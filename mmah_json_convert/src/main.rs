@@ -1,30 +1,19 @@
-extern crate serde_derive;
 extern crate base64;
-extern crate bincode;
+extern crate hanzi_lookup;
 
-use serde_derive::{Serialize, Deserialize};
-use serde_json::{Result, Value};
+use serde_json::Value;
 use std::fs::File;
+use hanzi_lookup::{CharData, Dataset, DatasetError, SubStrokeTriple};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct SubStrokeTriple {
-	dir: u8,
-	length: u8,
-	center: u8,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct CharData {
-	hanzi: char,
-	stroke_count: u16,
-	substrokes: Vec<SubStrokeTriple>,
-}
+const DATASET_NAME: &str = "mmah";
+const DATASET_SOURCE: &str = "Make Me a Hanzi graphics.txt via HanziLookupJS mmah.json; derived from Arphic PL KaitiM GB and Arphic PL UKai. Licensed under the Arphic Public License. Stroke boundaries inferred from substroke geometry.";
+// Share of characters whose inferred strokes must join up; any fewer, and the inference has gone wrong somewhere
+const MIN_JOINING_SHARE: f32 = 0.99;
 
-fn parse_json_strokes(fname: &str) -> Result<Vec<CharData>> {
-    let file = File::open(fname)
-        .expect("file should open read only");
+fn parse_json_strokes(fname: &str) -> Result<Vec<CharData>, DatasetError> {
+    let file = File::open(fname)?;
     let json: serde_json::Value = serde_json::from_reader(file)
-        .expect("file should be proper JSON");
+        .map_err(|err| DatasetError::Corrupt(format!("{} is not proper JSON: {}", fname, err)))?;
 
     let mut res: Vec<CharData> = Vec::new();    
 
@@ -39,19 +28,17 @@ fn parse_json_strokes(fname: &str) -> Result<Vec<CharData>> {
         for x in chars {
             // Each character is an array of four items like this: ["丿",1,2,0]
             // Character / Stroke Count / Substroke Count / First-substroke-index in byte array
-            let mut char_data = CharData {
-                hanzi: ' ',
-                stroke_count: 0,
-                substrokes: Vec::new(),
-            };
+            let mut hanzi = ' ';
+            let mut stroke_count: u16 = 0;
+            let mut sub_strokes: Vec<SubStrokeTriple> = Vec::new();
             // Get our character
             if let Value::String(chr) = &x[0] {
                 let first_char = chr.chars().next().unwrap();
-                char_data.hanzi = first_char;
+                hanzi = first_char;
             }
             // Stroke count
             if let Value::Number(val) = &x[1] {
-                char_data.stroke_count = val.as_u64().unwrap() as u16;
+                stroke_count = val.as_u64().unwrap() as u16;
             }
             // Substroke count
             let mut substroke_count: u64 = 0;
@@ -70,17 +57,80 @@ fn parse_json_strokes(fname: &str) -> Result<Vec<CharData>> {
                     length: bytes[(start_ix + cnt * 3 + 1) as usize],
                     center: bytes[(start_ix + cnt * 3 + 2) as usize],
                 };
-                char_data.substrokes.push(sst);
+                sub_strokes.push(sst);
             }
-            // Append to result
-            res.push(char_data);
+            // The JSON doesn't say which substrokes form which stroke, so the boundaries are inferred from their geometry.
+            // A character whose strokes can't all be found stops the conversion rather than get a wrong grouping.
+            res.push(CharData::with_inferred_strokes(hanzi, stroke_count, sub_strokes)?);
+        }
+    }
+    Ok(res)
+}
+
+// Each variant is an object like {"hanzi": "火", "tag": "alt-order", "stroke_order": [2, 3, 0, 1]}:
+// the character's standard template with its strokes written in the given order.
+// Instead of stroke_order, a variant may give its own "strokes", each a list of [dir, length, center] substrokes,
+// such as for a region's form of the character: {"hanzi": "骨", "region": "TW", "strokes": [[[192, 120, 52], ...], ...]}
+// Errors name the entry by its index in the file, and by its character once that is known.
+fn parse_json_variants(fname: &str, chars: &[CharData]) -> Result<Vec<CharData>, DatasetError> {
+    let file = File::open(fname)?;
+    let json: serde_json::Value = serde_json::from_reader(file)
+        .map_err(|err| DatasetError::Corrupt(format!("{} is not proper JSON: {}", fname, err)))?;
+    let variants = match &json {
+        Value::Array(variants) => variants,
+        _ => return Err(DatasetError::Corrupt(format!("{} should be an array of variants.", fname))),
+    };
+
+    let mut res: Vec<CharData> = Vec::new();
+    for (ix, x) in variants.iter().enumerate() {
+        let hanzi = x["hanzi"].as_str().and_then(|hanzi| hanzi.chars().next())
+            .ok_or_else(|| DatasetError::Corrupt(format!("Variant {} doesn't name its character.", ix)))?;
+        let bad_entry = |msg: String| DatasetError::Corrupt(format!("Variant {} ({}): {}", ix, hanzi, msg));
+        let mut variant = if x["stroke_order"].is_array() {
+            let order: Vec<usize> = serde_json::from_value(x["stroke_order"].clone())
+                .map_err(|err| bad_entry(format!("stroke_order should list stroke indexes: {}", err)))?;
+            let standard = chars.iter().find(|char_data| char_data.hanzi == hanzi && char_data.tag.is_none())
+                .ok_or_else(|| bad_entry("the character is not in the dataset.".to_string()))?;
+            standard.reordered(&order, "")
+                .ok_or_else(|| bad_entry(format!("stroke_order should list each of the {} strokes once.", standard.stroke_count)))?
+        }
+        else if x["strokes"].is_array() {
+            let strokes: Vec<Vec<[u8; 3]>> = serde_json::from_value(x["strokes"].clone())
+                .map_err(|err| bad_entry(format!("strokes should be lists of [dir, length, center] substrokes: {}", err)))?;
+            let strokes = strokes.into_iter()
+                .map(|stroke| stroke.into_iter().map(|sst| SubStrokeTriple { dir: sst[0], length: sst[1], center: sst[2] }).collect())
+                .collect();
+            CharData::from_strokes(hanzi, strokes)?
         }
+        else {
+            return Err(bad_entry("either stroke_order or strokes is needed.".to_string()));
+        };
+        variant.tag = x["tag"].as_str().map(|tag| tag.to_string());
+        variant.region = x["region"].as_str().map(|region| region.to_string());
+        res.push(variant);
     }
     Ok(res)
 }
 
 fn main() {
-    let char_data = parse_json_strokes("./data/mmah.json").expect("Failed to parse json.");
-    let mut f = File::create("./data/mmah.bin").expect("Failed to create binary file.");
-    bincode::serialize_into(&mut f, &char_data).expect("Failed to serialize into binary file.");
+    let mut char_data = parse_json_strokes("./data/mmah.json").unwrap_or_else(|err| {
+        eprintln!("Failed to parse json. {}", err);
+        std::process::exit(1);
+    });
+    // The JSON has no stroke boundaries to check the inferred ones against, but within a stroke, substrokes must join up
+    let joining = char_data.iter().filter(|char_data| char_data.strokes_join_up()).count();
+    let joining_share = joining as f32 / char_data.len() as f32;
+    println!("Inferred strokes join up in {} of {} characters ({:.2}%).", joining, char_data.len(), joining_share * 100.0);
+    if joining_share < MIN_JOINING_SHARE {
+        eprintln!("Too few characters have strokes that join up; at least {:.0}% should.", MIN_JOINING_SHARE * 100.0);
+        std::process::exit(1);
+    }
+    let variants = parse_json_variants("./data/variants.json", &char_data).unwrap_or_else(|err| {
+        eprintln!("Failed to parse variants. {}", err);
+        std::process::exit(1);
+    });
+    char_data.extend(variants);
+    let dataset = Dataset::new(DATASET_NAME, DATASET_SOURCE, char_data);
+    let f = File::create("./data/mmah.bin").expect("Failed to create binary file.");
+    dataset.write_to(f).expect("Failed to write binary file.");
 }
@@ -6,8 +6,15 @@ onmessage = (e) => {
     });
   }
   else if ("strokes" in e.data) {
-    const json = wasm_bindgen.lookup(e.data.strokes, e.data.limit);
-    const matches = JSON.parse(json);
+    // Lookup throws on malformed input, e.g. a stroke that is a single tap
+    let matches = [];
+    try {
+      const json = wasm_bindgen.lookup(e.data.strokes, e.data.limit);
+      matches = JSON.parse(json);
+    }
+    catch (err) {
+      console.log(err);
+    }
     postMessage({ what: "lookup", matches: matches });
   }
 };